@@ -1,5 +1,5 @@
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
-use cw_sn_dex_aggregator::msg::{HandleMsg, InitMsg, QueryMsg};
+use cw_sn_dex_aggregator::msg::{HandleMsg, InitMsg, QueryMsg, SwapResult};
 use std::env::current_dir;
 use std::fs::create_dir_all;
 
@@ -12,4 +12,5 @@ fn main() {
     export_schema(&schema_for!(InitMsg), &out_dir);
     export_schema(&schema_for!(HandleMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(SwapResult), &out_dir);
 }
@@ -1,5 +1,6 @@
-use crate::state::{Hop, SecretContract, Token};
+use crate::state::{Config, Hop, Protocol, SecretContract, Token};
 use cosmwasm_std::{Env, HumanAddr, StdError, StdResult, Uint128};
+use std::collections::VecDeque;
 
 pub fn authorize(expected: HumanAddr, received: HumanAddr) -> StdResult<()> {
     if expected != received {
@@ -20,6 +21,8 @@ pub fn validate_received_from_an_allowed_address(
             // 1. wrapped (redeem_denom present) - from must be from this contract
             // 2. from migration contract - from must be from this contract
             // 3. shade_protocol_router_path - from must be current_hop smart contract
+            // 4. sienna_swap_router_path - from must be current_hop smart contract (the router sends the final token)
+            // 5. pair (SecretSwap, ShadeSwap or SiennaSwap) - from must be current_hop smart contract
             if current_hop.redeem_denom.is_some() || current_hop.migrate_to_token.is_some() {
                 authorize(env.contract.address.clone(), from)?;
             } else if current_hop.smart_contract.is_some() {
@@ -35,14 +38,21 @@ pub fn validate_received_from_an_allowed_address(
     Ok(())
 }
 
-pub fn validate_received_token(token: Token, amount: Uint128, env: &Env) -> StdResult<()> {
+pub fn validate_received_token(
+    token: Token,
+    denom: Option<String>,
+    amount: Uint128,
+    env: &Env,
+) -> StdResult<()> {
     let token_valid: bool = match token {
         Token::Snip20(SecretContract {
             ref address,
             contract_hash: _,
         }) => env.message.sender == *address,
         Token::Native(_) => {
-            env.message.sent_funds.len() == 1 && env.message.sent_funds[0].amount == amount
+            env.message.sent_funds.len() == 1
+                && Some(&env.message.sent_funds[0].denom) == denom.as_ref()
+                && env.message.sent_funds[0].amount == amount
         }
     };
 
@@ -55,6 +65,50 @@ pub fn validate_received_token(token: Token, amount: Uint128, env: &Env) -> StdR
     Ok(())
 }
 
+// Redeem, deposit and migration hops are paid out of this contract's own balance,
+// so only the admin's whitelisted tokens and migration contracts can be used
+pub fn validate_whitelisted_hops<'a>(
+    config: &Config,
+    hops: impl IntoIterator<Item = &'a Hop>,
+) -> StdResult<()> {
+    for hop in hops {
+        let token: &HumanAddr = match hop.from_token {
+            Token::Snip20(SecretContract { ref address, .. }) => address,
+            Token::Native(SecretContract { ref address, .. }) => address,
+        };
+        if let Some(migrate_to_token) = &hop.migrate_to_token {
+            if !config.migrations.iter().any(|migration| {
+                migration.from_token == *token
+                    && Some(&migration.smart_contract)
+                        == hop
+                            .smart_contract
+                            .as_ref()
+                            .map(|contract| &contract.address)
+                    && migration.migrate_to_token == migrate_to_token.address
+            }) {
+                return Err(StdError::generic_err("Migration isn't whitelisted."));
+            }
+        }
+        // Native coins are staked directly, so the derivative is whitelisted with the denom
+        let wrapped_token_address: Option<&HumanAddr> = match hop.from_token {
+            Token::Native(_) if hop.protocol == Some(Protocol::StakingDerivative) => hop
+                .smart_contract
+                .as_ref()
+                .map(|contract| &contract.address),
+            _ => Some(token),
+        };
+        if let Some(denom) = &hop.redeem_denom {
+            if !config.wrapped_tokens.iter().any(|wrapped_token| {
+                Some(&wrapped_token.token) == wrapped_token_address && wrapped_token.denom == *denom
+            }) {
+                return Err(StdError::generic_err("Wrapped token isn't whitelisted."));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn validate_user_is_the_receiver(
     token: Token,
     from: HumanAddr,
@@ -72,3 +126,63 @@ pub fn validate_user_is_the_receiver(
 
     Ok(())
 }
+
+// A third party recipient must be explicitly requested in the route.
+// It can't be this contract or any contract that the route interacts with,
+// as tokens sent there would be stuck or lost.
+pub fn validate_third_party_recipient(
+    hops: &VecDeque<Hop>,
+    to: HumanAddr,
+    env: &Env,
+) -> StdResult<()> {
+    if to == env.contract.address {
+        return Err(StdError::generic_err("Recipient can't be this contract."));
+    }
+    for hop in hops {
+        let token_address: &HumanAddr = match hop.from_token {
+            Token::Snip20(SecretContract { ref address, .. }) => address,
+            Token::Native(SecretContract { ref address, .. }) => address,
+        };
+        let is_hop_smart_contract: bool = match hop.smart_contract {
+            Some(SecretContract { ref address, .. }) => to == *address,
+            None => false,
+        };
+        if to == *token_address || is_hop_smart_contract {
+            return Err(StdError::generic_err(
+                "Recipient can't be a smart contract in the route.",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Arbitrage routes are cyclic so that the output can be compared with the input
+pub fn validate_arbitrage_route(hops: &VecDeque<Hop>) -> StdResult<()> {
+    if hops.front().map(|hop| &hop.from_token) != hops.back().map(|hop| &hop.from_token) {
+        return Err(StdError::generic_err(
+            "Arbitrage route must start and end with the same token.",
+        ));
+    }
+
+    Ok(())
+}
+
+// Routes run by keepers for stored orders must swap the escrowed token into the owner's token.
+// The first hop's denom is the escrowed coin, so it can't spend another escrowed denom.
+pub fn validate_route_matches_template(
+    hops: &VecDeque<Hop>,
+    template_hops: &VecDeque<Hop>,
+) -> StdResult<()> {
+    if hops.front().map(|hop| &hop.from_token) != template_hops.front().map(|hop| &hop.from_token)
+        || hops.front().map(|hop| &hop.redeem_denom)
+            != template_hops.front().map(|hop| &hop.redeem_denom)
+        || hops.back().map(|hop| &hop.from_token) != template_hops.back().map(|hop| &hop.from_token)
+    {
+        return Err(StdError::generic_err(
+            "Route must start and end with the order's tokens.",
+        ));
+    }
+
+    Ok(())
+}
@@ -1,3 +1,48 @@
 pub const BLOCK_SIZE: usize = 256;
+// Logs left out of private routes
+pub const AMOUNT_LOG_KEYS: [&str; 4] = [
+    "route_input_amount",
+    "hop_amount_in",
+    "fee_amount",
+    "return_amount",
+];
 pub const CONFIG_KEY: &[u8] = b"config";
+// Every kind that hop_kind can log
+pub const HOP_KINDS: [&str; 10] = [
+    "secret_swap",
+    "shade_swap",
+    "sienna_swap",
+    "sienna_swap_router",
+    "shade_protocol_router",
+    "migration",
+    "withdraw_liquidity",
+    "staking_derivative",
+    "redeem",
+    "deposit",
+];
+// Keepers that execute limit orders are paid 0.1% of the output
+pub const KEEPER_FEE_BASIS_POINTS: u128 = 10;
+// Pair oracles convert between tokens with at most 18 decimals
+pub const MAX_ORACLE_DECIMALS: u8 = 18;
+// Oracle rates are in 18 decimals
+pub const ORACLE_RATE_PRECISION: u128 = 1_000_000_000_000_000_000;
+pub static KEY_CONTRACT_VIEWING_KEY: &[u8] = b"contract_viewing_key";
 pub static KEY_ROUTE_STATE: &[u8] = b"route_state";
+pub static KEY_DCA_COUNT: &[u8] = b"dca_count";
+pub static KEY_ORDER_COUNT: &[u8] = b"order_count";
+pub static KEY_PRNG_SEED: &[u8] = b"prng_seed";
+pub static KEY_ROUTE_COUNT: &[u8] = b"route_count";
+pub static PREFIX_DCAS: &[u8] = b"dcas";
+pub static PREFIX_ESCROWED_AMOUNTS: &[u8] = b"escrowed_amounts";
+pub static PREFIX_HOP_COUNTS: &[u8] = b"hop_counts";
+pub static PREFIX_MULTI_INPUT_COLLECTIONS: &[u8] = b"multi_input_collections";
+pub static PREFIX_PAIR_ORACLES: &[u8] = b"pair_oracles";
+pub const PREFIX_REVOKED_PERMITS: &str = "revoked_permits";
+pub static PREFIX_SWAP_HISTORY: &[u8] = b"swap_history";
+pub static PREFIX_TOKENS: &[u8] = b"tokens";
+pub static PREFIX_TOKEN_ADDRESSES: &[u8] = b"token_addresses";
+pub static PREFIX_TOKEN_STATS: &[u8] = b"token_stats";
+pub static PREFIX_ORDERS: &[u8] = b"orders";
+pub static PREFIX_USER_DCAS: &[u8] = b"user_dcas";
+pub static PREFIX_USER_ORDERS: &[u8] = b"user_orders";
+pub static PREFIX_VIEWING_KEYS: &[u8] = b"viewing_keys";
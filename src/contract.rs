@@ -1,33 +1,77 @@
 use crate::authorize::{
-    authorize, validate_received_from_an_allowed_address, validate_received_token,
-    validate_user_is_the_receiver,
+    authorize, validate_arbitrage_route, validate_received_from_an_allowed_address,
+    validate_received_token, validate_route_matches_template, validate_third_party_recipient,
+    validate_user_is_the_receiver, validate_whitelisted_hops,
+};
+use crate::constants::{
+    AMOUNT_LOG_KEYS, BLOCK_SIZE, CONFIG_KEY, HOP_KINDS, KEEPER_FEE_BASIS_POINTS,
+    MAX_ORACLE_DECIMALS, ORACLE_RATE_PRECISION, PREFIX_REVOKED_PERMITS,
 };
-use crate::constants::{BLOCK_SIZE, CONFIG_KEY};
 use crate::{
-    msg::{HandleMsg, InitMsg, QueryMsg, ShadeProtocol, Snip20, Snip20Swap},
+    asset::{Asset, AssetInfo},
+    msg::{
+        ContractInfo, ContractLimits, ContractStatus, HandleAnswer, HandleMsg, InitMsg,
+        OracleQuery, OracleReferenceData, QueryMsg, QueryWithPermit, ReceiveMsg, ResponseStatus,
+        SecretSwapPair, SecretSwapPairQuery, SecretSwapPoolResponse, ShadeProtocol,
+        ShadeProtocolPair, SiennaSwapPair, SiennaSwapRoute, Snip20, Snip20Swap, StakingDerivative,
+        SwapManyRoute, SwapResult,
+    },
     state::{
-        delete_route_state, read_route_state, store_route_state, Config, Hop, Route, RouteState,
-        SecretContract, Token,
+        add_escrowed_amount, append_swap, delete_dca, delete_multi_input_collection, delete_order,
+        delete_route_state, next_dca_id, next_order_id, read_contract_viewing_key, read_dca,
+        read_escrowed_amount, read_hop_count, read_multi_input_collection, read_order,
+        read_pair_oracle, read_prng_seed, read_route_count, read_route_state, read_swaps,
+        read_token_addresses, read_token_metadata, read_token_stats, read_user_dca_ids,
+        read_user_order_ids, read_viewing_key, remove_escrowed_amount, store_contract_viewing_key,
+        store_dca, store_hop_count, store_multi_input_collection, store_order, store_pair_oracle,
+        store_prng_seed, store_route_count, store_route_state, store_token_metadata,
+        store_token_stats, store_viewing_key, Config, Dca, DcaPeriod, DcaSchedule, Hop, HopCount,
+        LimitOrder, Migration, MultiInputCollection, MultiInputRoute, PairOracle, Protocol, Route,
+        RouteInput, RouteState, SecretContract, Stats, Swap, Token, TokenAmount, TokenMetadata,
+        TokenStats, Trigger, TriggerKind, WrappedToken, ZapIn,
     },
+    viewing_key::ViewingKey,
 };
 use cosmwasm_std::{
-    from_binary, log, to_binary, Api, BankMsg, Binary, Coin, CosmosMsg, Env, Extern,
-    HandleResponse, HumanAddr, InitResponse, Querier, StdError, StdResult, Storage, Uint128,
-    WasmMsg,
+    from_binary, log, to_binary, Api, BalanceResponse, BankMsg, BankQuery, Binary, Coin, CosmosMsg,
+    Env, Extern, HandleResponse, HumanAddr, InitResponse, LogAttribute, Querier, QueryRequest,
+    StdError, StdResult, Storage, Uint128, WasmMsg,
 };
+use secret_toolkit::crypto::sha_256;
+use secret_toolkit::permit::{self, Permit, RevokedPermits, TokenPermissions};
 use secret_toolkit::snip20;
 use secret_toolkit::storage::{TypedStore, TypedStoreMut};
+use secret_toolkit::utils::{pad_handle_result, HandleCallback, Query};
+use std::collections::VecDeque;
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    _msg: InitMsg,
+    msg: InitMsg,
 ) -> StdResult<InitResponse> {
     let mut config_store = TypedStoreMut::attach(&mut deps.storage);
     let config: Config = Config {
-        admin: env.message.sender,
+        admin: env.message.sender.clone(),
+        contract_address: env.contract.address.clone(),
+        keepers: vec![],
+        migrations: msg.migrations.unwrap_or_default(),
+        pad_responses: msg.pad_responses.unwrap_or(true),
+        wrapped_tokens: msg.wrapped_tokens.unwrap_or_default(),
     };
     config_store.store(CONFIG_KEY, &config)?;
+    // Viewing keys are generated from this seed and the user's entropy
+    let prng_seed: [u8; 32] = sha_256(
+        &[
+            env.block.height.to_be_bytes().to_vec(),
+            env.block.time.to_be_bytes().to_vec(),
+            env.contract.address.0.as_bytes().to_vec(),
+            msg.entropy.as_bytes().to_vec(),
+        ]
+        .concat(),
+    );
+    store_prng_seed(&mut deps.storage, &prng_seed);
+    let contract_viewing_key: ViewingKey = ViewingKey::new(&env, &prng_seed, b"contract");
+    store_contract_viewing_key(&mut deps.storage, &contract_viewing_key.0);
 
     Ok(InitResponse {
         messages: vec![],
@@ -40,25 +84,110 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
-    match msg {
+    let response: StdResult<HandleResponse> = match msg {
         HandleMsg::Receive {
             from,
             msg: Some(msg),
             amount,
-        } => handle_first_hop(deps, &env, from, msg, amount),
+        } => match from_binary(&msg) {
+            Ok(ReceiveMsg::CollectInput {}) => collect_input(deps, &env, from, amount),
+            Ok(ReceiveMsg::CreateDca {
+                route_template,
+                schedule,
+            }) => create_dca(deps, &env, from, amount, route_template, schedule),
+            Ok(ReceiveMsg::PlaceLimitOrder {
+                route_template,
+                min_output,
+                expiration,
+            }) => place_order(
+                deps,
+                &env,
+                from,
+                LimitOrder {
+                    id: 0,
+                    owner: route_template.to.clone(),
+                    route_template,
+                    amount,
+                    denom: None,
+                    min_output,
+                    expiration,
+                    trigger: None,
+                },
+            ),
+            Ok(ReceiveMsg::PlaceTriggerOrder {
+                route_template,
+                trigger,
+                expiration,
+            }) => place_order(
+                deps,
+                &env,
+                from,
+                LimitOrder {
+                    id: 0,
+                    owner: route_template.to.clone(),
+                    min_output: route_template.minimum_acceptable_amount,
+                    route_template,
+                    amount,
+                    denom: None,
+                    expiration,
+                    trigger: Some(trigger),
+                },
+            ),
+            Err(_) => handle_first_hop(deps, &env, from, msg, amount),
+        },
         HandleMsg::Receive {
             from,
             msg: None,
             amount,
         } => handle_hop(deps, &env, from, amount),
+        HandleMsg::CancelDca { id } => cancel_dca(deps, &env, id),
+        HandleMsg::CancelMultiInputRoute {} => cancel_multi_input_route(deps, &env),
+        HandleMsg::CancelOrder { id } => cancel_order(deps, &env, id),
+        HandleMsg::CreateMultiInputRoute { route } => create_multi_input_route(deps, &env, route),
+        HandleMsg::CreateViewingKey { entropy, .. } => create_viewing_key(deps, &env, entropy),
+        HandleMsg::ExecuteArbitrage { amount, route } => {
+            execute_arbitrage(deps, &env, amount, route)
+        }
+        HandleMsg::ExecuteDca { id, route } => execute_dca(deps, &env, id, route),
+        HandleMsg::ExecuteOrder { id, route } => execute_order(deps, &env, id, route),
         HandleMsg::FinalizeRoute {} => finalize_route(deps, &env),
-        HandleMsg::RegisterTokens { tokens } => register_tokens(&env, tokens),
+        HandleMsg::RecordBalance { token } => record_balance(deps, &env, token),
+        HandleMsg::RefreshTokens { tokens } => refresh_tokens(deps, &env, tokens),
+        HandleMsg::RegisterTokens { tokens } => register_tokens(deps, &env, tokens),
         HandleMsg::RescueTokens {
             amount,
             denom,
             token,
         } => rescue_tokens(deps, &env, amount, denom, token),
+        HandleMsg::RevokePermit { permit_name, .. } => revoke_permit(deps, &env, permit_name),
+        HandleMsg::SetKeepers { keepers } => set_keepers(deps, &env, keepers),
+        HandleMsg::SetMigrations { migrations } => set_migrations(deps, &env, migrations),
+        HandleMsg::SetPairOracle {
+            input_token,
+            output_token,
+            pair_oracle,
+        } => set_pair_oracle(deps, &env, input_token, output_token, pair_oracle),
+        HandleMsg::SendMintedBalance { token } => send_minted_balance(deps, &env, token),
+        HandleMsg::SetViewingKey { key, .. } => set_viewing_key(deps, &env, key),
+        HandleMsg::SetWrappedTokens { wrapped_tokens } => {
+            set_wrapped_tokens(deps, &env, wrapped_tokens)
+        }
+        HandleMsg::StartRoute {
+            amount,
+            route,
+            user,
+        } => {
+            authorize(env.contract.address.clone(), env.message.sender.clone())?;
+            start_route(deps, &env, route, amount, user, None)
+        }
+        HandleMsg::SwapMany { routes } => swap_many(&env, routes),
+    };
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    if config.pad_responses {
+        return pad_handle_result(response, BLOCK_SIZE);
     }
+
+    response
 }
 
 fn hop_messages(hop: Hop, amount: Uint128, env: &Env) -> StdResult<Vec<CosmosMsg>> {
@@ -68,7 +197,21 @@ fn hop_messages(hop: Hop, amount: Uint128, env: &Env) -> StdResult<Vec<CosmosMsg
             address,
             contract_hash,
         }) => {
-            if hop.shade_protocol_router_path.is_some() {
+            if hop.sienna_swap_router_path.is_some() {
+                messages.push(snip20::send_msg(
+                    hop.smart_contract.unwrap().address,
+                    amount,
+                    Some(to_binary(&SiennaSwapRoute {
+                        hops: hop.sienna_swap_router_path.unwrap(),
+                        expected_return: hop.minimum_output,
+                        to: env.contract.address.clone(),
+                    })?),
+                    None,
+                    BLOCK_SIZE,
+                    contract_hash,
+                    address,
+                )?);
+            } else if hop.shade_protocol_router_path.is_some() {
                 messages.push(snip20::send_msg(
                     hop.smart_contract.unwrap().address,
                     amount,
@@ -99,6 +242,33 @@ fn hop_messages(hop: Hop, amount: Uint128, env: &Env) -> StdResult<Vec<CosmosMsg
                     hop.migrate_to_token.clone().unwrap().contract_hash,
                     hop.migrate_to_token.unwrap().address,
                 )?);
+            } else if hop.protocol == Some(Protocol::WithdrawLiquidity) {
+                messages.push(snip20::send_msg(
+                    hop.smart_contract.unwrap().address,
+                    amount,
+                    Some(to_binary(&Snip20Swap::WithdrawLiquidity {})?),
+                    None,
+                    BLOCK_SIZE,
+                    contract_hash,
+                    address,
+                )?);
+            } else if hop.protocol == Some(Protocol::StakingDerivative) {
+                messages.push(snip20::redeem_msg(
+                    amount,
+                    hop.redeem_denom.clone(),
+                    None,
+                    BLOCK_SIZE,
+                    contract_hash,
+                    address,
+                )?);
+                messages.extend(staking_derivative_messages(
+                    hop.smart_contract.unwrap(),
+                    Coin {
+                        amount,
+                        denom: hop.redeem_denom.unwrap(),
+                    },
+                    env,
+                )?);
             } else if hop.redeem_denom.is_some() {
                 messages.push(snip20::redeem_msg(
                     amount,
@@ -123,14 +293,26 @@ fn hop_messages(hop: Hop, amount: Uint128, env: &Env) -> StdResult<Vec<CosmosMsg
                     }],
                 }))
             } else {
+                // expected_return is only set for hops with a minimum_output,
+                // otherwise slippage is only checked at the end of the route
+                let swap_msg: Binary = match hop.protocol {
+                    Some(Protocol::ShadeSwap) => to_binary(&ShadeProtocolPair::SwapTokens {
+                        expected_return: hop.minimum_output,
+                        to: Some(env.contract.address.to_string()),
+                    })?,
+                    Some(Protocol::SiennaSwap) => to_binary(&SiennaSwapPair::Swap {
+                        expected_return: hop.minimum_output,
+                        to: Some(env.contract.address.clone()),
+                    })?,
+                    _ => to_binary(&Snip20Swap::Swap {
+                        expected_return: hop.minimum_output,
+                        to: Some(env.contract.address.clone()),
+                    })?,
+                };
                 messages.push(snip20::send_msg(
                     hop.smart_contract.unwrap().address,
                     amount,
-                    Some(to_binary(&Snip20Swap::Swap {
-                        // set expected_return to None because we don't care about slippage mid-route
-                        expected_return: None,
-                        to: Some(env.contract.address.clone()),
-                    })?),
+                    Some(swap_msg),
                     None,
                     BLOCK_SIZE,
                     contract_hash,
@@ -138,6 +320,16 @@ fn hop_messages(hop: Hop, amount: Uint128, env: &Env) -> StdResult<Vec<CosmosMsg
                 )?);
             }
         }
+        Token::Native(_) if hop.protocol == Some(Protocol::StakingDerivative) => {
+            messages.extend(staking_derivative_messages(
+                hop.smart_contract.unwrap(),
+                Coin {
+                    amount,
+                    denom: hop.redeem_denom.unwrap(),
+                },
+                env,
+            )?);
+        }
         Token::Native(SecretContract {
             address,
             contract_hash,
@@ -165,6 +357,226 @@ fn hop_messages(hop: Hop, amount: Uint128, env: &Env) -> StdResult<Vec<CosmosMsg
     Ok(messages)
 }
 
+// The expected output at the oracle's rate, less the allowed deviation
+fn oracle_minimum_amount<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hops: &VecDeque<Hop>,
+    amount: Uint128,
+) -> StdResult<Option<Uint128>> {
+    let input_token: SecretContract = token_contract(hops[0].from_token.clone());
+    let output_token: SecretContract = token_contract(hops[hops.len() - 1].from_token.clone());
+    let pair_oracle: PairOracle =
+        match read_pair_oracle(&deps.storage, &input_token.address, &output_token.address)? {
+            Some(pair_oracle) => pair_oracle,
+            None => return Ok(None),
+        };
+    let reference_data: OracleReferenceData = OracleQuery::GetReferenceData {
+        base_symbol: pair_oracle.base_symbol,
+        quote_symbol: pair_oracle.quote_symbol,
+    }
+    .query(
+        &deps.querier,
+        pair_oracle.oracle.contract_hash,
+        pair_oracle.oracle.address,
+    )?;
+    // One ratio so that the amount isn't truncated before it's multiplied by the rate,
+    // decimals are at most 18 so the powers of ten fit.
+    // The amount times the rate doesn't fit in u128 for 18 decimal tokens, so the ratio is 256 bit.
+    let (nominator, denominator): (Option<u128>, u128) =
+        if pair_oracle.output_decimals >= pair_oracle.input_decimals {
+            (
+                reference_data.rate.u128().checked_mul(
+                    10u128.pow((pair_oracle.output_decimals - pair_oracle.input_decimals).into()),
+                ),
+                ORACLE_RATE_PRECISION,
+            )
+        } else {
+            (
+                Some(reference_data.rate.u128()),
+                ORACLE_RATE_PRECISION
+                    * 10u128.pow((pair_oracle.input_decimals - pair_oracle.output_decimals).into()),
+            )
+        };
+    let expected_amount: Uint128 = match nominator {
+        Some(nominator) => wide_multiply_ratio(amount, nominator, denominator)?,
+        None => return Err(StdError::generic_err("Oracle rate is too large.")),
+    };
+
+    Ok(Some(wide_multiply_ratio(
+        expected_amount,
+        10_000u128.saturating_sub(pair_oracle.max_deviation_basis_points.into()),
+        10_000u128,
+    )?))
+}
+
+// Stake the native coin into the derivative contract and then send the minted derivative to itself.
+// Minting doesn't call Receive, so the minted amount is the increase in this contract's balance.
+fn staking_derivative_messages(
+    derivative: SecretContract,
+    coin: Coin,
+    env: &Env,
+) -> StdResult<Vec<CosmosMsg>> {
+    Ok(vec![
+        self_msg(
+            env,
+            &HandleMsg::RecordBalance {
+                token: derivative.clone(),
+            },
+        )?,
+        StakingDerivative::Stake {}.to_cosmos_msg(
+            BLOCK_SIZE,
+            derivative.contract_hash.clone(),
+            derivative.address.clone(),
+            Some(coin),
+        )?,
+        self_msg(env, &HandleMsg::SendMintedBalance { token: derivative })?,
+    ])
+}
+
+fn self_msg(env: &Env, msg: &HandleMsg) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        callback_code_hash: env.contract_code_hash.clone(),
+        msg: to_binary(msg)?,
+        send: vec![],
+    }))
+}
+
+fn record_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    token: SecretContract,
+) -> StdResult<HandleResponse> {
+    authorize(env.contract.address.clone(), env.message.sender.clone())?;
+    let mut route_state: RouteState = match read_route_state(&deps.storage)? {
+        Some(route_state) => route_state,
+        None => return Err(StdError::generic_err("cannot find route")),
+    };
+    route_state.balance_before_mint = Some(contract_balance(deps, env, &token)?);
+    store_route_state(&mut deps.storage, &route_state)?;
+
+    Ok(HandleResponse::default())
+}
+
+fn send_minted_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    token: SecretContract,
+) -> StdResult<HandleResponse> {
+    authorize(env.contract.address.clone(), env.message.sender.clone())?;
+    let mut route_state: RouteState = match read_route_state(&deps.storage)? {
+        Some(route_state) => route_state,
+        None => return Err(StdError::generic_err("cannot find route")),
+    };
+    let balance_before_mint: Uint128 = match route_state.balance_before_mint.take() {
+        Some(balance_before_mint) => balance_before_mint,
+        None => {
+            return Err(StdError::generic_err(
+                "Balance wasn't recorded before minting.",
+            ))
+        }
+    };
+    let minted_amount: Uint128 = (contract_balance(deps, env, &token)? - balance_before_mint)?;
+    let mut logs = vec![];
+    let mut data: Option<Binary> = None;
+    // A zap in mints the route's output, which is sent to the user
+    let recipient: HumanAddr = if route_state.remaining_route.hops.is_empty() {
+        let fee_token: HumanAddr =
+            token_contract(route_state.current_hop.from_token.clone()).address;
+        route_state.swap.output = TokenAmount {
+            token: token.address.clone(),
+            amount: minted_amount,
+        };
+        logs = public_logs(
+            payout_logs(&route_state.remaining_route.to, &route_state.swap.output),
+            route_state.remaining_route.private,
+        );
+        data = Some(swap_result(&route_state.swap, route_state.hop_index)?);
+        append_swap(&mut deps.storage, &route_state.user, &route_state.swap)?;
+        record_route_stats(&mut deps.storage, &route_state.swap, fee_token)?;
+        route_state.remaining_route.to.clone()
+    } else {
+        env.contract.address.clone()
+    };
+    store_route_state(&mut deps.storage, &route_state)?;
+
+    Ok(HandleResponse {
+        messages: vec![snip20::send_msg(
+            recipient,
+            minted_amount,
+            None,
+            None,
+            BLOCK_SIZE,
+            token.contract_hash,
+            token.address,
+        )?],
+        log: logs,
+        data,
+    })
+}
+
+// The token must be registered so that this contract's viewing key is set on it
+fn contract_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    token: &SecretContract,
+) -> StdResult<Uint128> {
+    Ok(snip20::balance_query(
+        &deps.querier,
+        env.contract.address.clone(),
+        read_contract_viewing_key(&deps.storage)?,
+        BLOCK_SIZE,
+        token.contract_hash.clone(),
+        token.address.clone(),
+    )?
+    .amount)
+}
+
+// Escrowed amounts are keyed by SNIP-20 address or native denom
+fn escrow_key(token: &Token, denom: &Option<String>) -> String {
+    match token {
+        Token::Snip20(SecretContract { address, .. }) => address.0.clone(),
+        Token::Native(_) => denom.clone().unwrap_or_default(),
+    }
+}
+
+// Only the part of the balance that isn't escrowed for users is protocol revenue,
+// token is None for the native denom
+fn validate_unescrowed_amount<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    token: Option<&SecretContract>,
+    denom: &Option<String>,
+    amount: Uint128,
+) -> StdResult<()> {
+    let key: String = match token {
+        Some(token) => token.address.0.clone(),
+        None => denom.clone().unwrap_or_default(),
+    };
+    let escrowed_amount: Uint128 = read_escrowed_amount(&deps.storage, &key)?;
+    if escrowed_amount.is_zero() {
+        return Ok(());
+    }
+    let balance: Uint128 = match token {
+        Some(token) => contract_balance(deps, env, token)?,
+        None => {
+            let balance_response: BalanceResponse =
+                deps.querier.query(&QueryRequest::Bank(BankQuery::Balance {
+                    address: env.contract.address.clone(),
+                    denom: key,
+                }))?;
+            balance_response.amount.amount
+        }
+    };
+    if amount > (balance - escrowed_amount).unwrap_or_else(|_| Uint128::zero()) {
+        return Err(StdError::generic_err(
+            "Amount is more than the balance that isn't escrowed.",
+        ));
+    }
+
+    Ok(())
+}
+
 // This is the first msg from the user, with the entire route details
 // 1. save the remaining route to state (e.g. if the route is X/Y -> Y/Z -> Z->W then save Y/Z -> Z/W to state)
 // 2. send `amount` X to pair X/Y
@@ -176,45 +588,297 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
     msg: Binary,
     amount: Uint128,
 ) -> StdResult<HandleResponse> {
-    let Route {
-        mut hops,
-        to,
-        estimated_amount,
-        minimum_acceptable_amount,
-    } = from_binary(&msg)?;
+    let route: Route = from_binary(&msg)?;
     //SECOND HOP MUST EXIST AS LAST HOP CHECKS MIN ACCEPTABLE AMOUNT
-    if hops.len() < 2 {
+    if route.hops.len() < 2 {
         return Err(StdError::generic_err("Route must have at least 2 hops."));
     }
 
-    let first_hop: Hop = hops.pop_front().unwrap();
-    validate_received_token(first_hop.from_token.clone(), amount, env)?;
-    validate_user_is_the_receiver(
+    let first_hop: &Hop = &route.hops[0];
+    validate_received_token(
         first_hop.from_token.clone(),
-        from,
-        to.clone(),
-        env.message.sender.clone(),
+        first_hop.redeem_denom.clone(),
+        amount,
+        env,
     )?;
+    let user: HumanAddr = match first_hop.from_token {
+        Token::Snip20(_) => from.clone(),
+        Token::Native(_) => env.message.sender.clone(),
+    };
+    if route.allow_third_party_recipient.unwrap_or(false) {
+        validate_third_party_recipient(&route.hops, route.to.clone(), env)?;
+    } else {
+        validate_user_is_the_receiver(
+            first_hop.from_token.clone(),
+            from,
+            route.to.clone(),
+            env.message.sender.clone(),
+        )?;
+    }
 
-    store_route_state(
+    start_route(deps, env, route, amount, user, None)
+}
+
+// Independent routes are funded up front and run one after another,
+// each route is finalized before the next one starts
+fn swap_many(env: &Env, routes: Vec<SwapManyRoute>) -> StdResult<HandleResponse> {
+    if routes.is_empty() {
+        return Err(StdError::generic_err(
+            "SwapMany must have at least 1 route.",
+        ));
+    }
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    let mut native_inputs: Vec<Coin> = vec![];
+    let mut logs = vec![log("route_count", routes.len().to_string())];
+    for (index, SwapManyRoute { amount, route }) in routes.into_iter().enumerate() {
+        if route.hops.len() < 2 {
+            return Err(StdError::generic_err("Route must have at least 2 hops."));
+        }
+        if route.allow_third_party_recipient.unwrap_or(false) {
+            validate_third_party_recipient(&route.hops, route.to.clone(), env)?;
+        } else {
+            authorize(env.message.sender.clone(), route.to.clone())?;
+        }
+
+        let first_hop: &Hop = &route.hops[0];
+        let input_token: SecretContract = token_contract(first_hop.from_token.clone());
+        match first_hop.from_token {
+            Token::Snip20(_) => messages.push(snip20::transfer_from_msg(
+                env.message.sender.clone(),
+                env.contract.address.clone(),
+                amount,
+                None,
+                BLOCK_SIZE,
+                input_token.contract_hash.clone(),
+                input_token.address.clone(),
+            )?),
+            Token::Native(_) => add_native_input(&mut native_inputs, first_hop, amount)?,
+        }
+        let output_token: SecretContract =
+            token_contract(route.hops.back().unwrap().from_token.clone());
+        if !route.private.unwrap_or(false) {
+            logs.push(log(
+                format!("route_{}", index),
+                format!(
+                    "{} {} -> {} minimum {}",
+                    amount,
+                    input_token.address,
+                    output_token.address,
+                    route.minimum_acceptable_amount
+                ),
+            ));
+        }
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.clone(),
+            callback_code_hash: env.contract_code_hash.clone(),
+            msg: to_binary(&HandleMsg::StartRoute {
+                amount,
+                route,
+                user: env.message.sender.clone(),
+            })?,
+            send: vec![],
+        }));
+    }
+    validate_sent_funds(env, &native_inputs)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+fn add_native_input(native_inputs: &mut Vec<Coin>, hop: &Hop, amount: Uint128) -> StdResult<()> {
+    let denom: String = match hop.redeem_denom.clone() {
+        Some(denom) => denom,
+        None => return Err(StdError::generic_err("Native hop must set redeem_denom.")),
+    };
+    match native_inputs.iter_mut().find(|coin| coin.denom == denom) {
+        Some(coin) => coin.amount += amount,
+        None => native_inputs.push(Coin { denom, amount }),
+    }
+
+    Ok(())
+}
+
+// Native inputs must be sent with the message, one coin per denom
+fn validate_sent_funds(env: &Env, native_inputs: &[Coin]) -> StdResult<()> {
+    if native_inputs.len() != env.message.sent_funds.len()
+        || native_inputs
+            .iter()
+            .any(|coin| !env.message.sent_funds.contains(coin))
+    {
+        return Err(StdError::generic_err(
+            "Sent funds must match the native routes' amounts.",
+        ));
+    }
+
+    Ok(())
+}
+
+// The sender's SNIP-20 inputs are collected before the multi-input route starts,
+// native inputs are sent with this message
+fn create_multi_input_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    route: MultiInputRoute,
+) -> StdResult<HandleResponse> {
+    if route.inputs.len() < 2 {
+        return Err(StdError::generic_err(
+            "Multi-input route must have at least 2 inputs.",
+        ));
+    }
+    if read_multi_input_collection(&deps.storage, &env.message.sender)?.is_some() {
+        return Err(StdError::generic_err(
+            "A multi-input route is already being collected.",
+        ));
+    }
+
+    let mut native_inputs: Vec<Coin> = vec![];
+    let mut received: Vec<bool> = vec![];
+    for input in &route.inputs {
+        if input.hops.len() < 2 {
+            return Err(StdError::generic_err("Route must have at least 2 hops."));
+        }
+        if input.hops.back().map(|hop| &hop.from_token)
+            != route.inputs[0].hops.back().map(|hop| &hop.from_token)
+        {
+            return Err(StdError::generic_err(
+                "Inputs must end with the same token.",
+            ));
+        }
+        if input
+            .hops
+            .iter()
+            .any(|hop| hop.protocol == Some(Protocol::WithdrawLiquidity))
+        {
+            return Err(StdError::generic_err(
+                "Multi-input routes can't withdraw liquidity.",
+            ));
+        }
+        if route.allow_third_party_recipient.unwrap_or(false) {
+            validate_third_party_recipient(&input.hops, route.to.clone(), env)?;
+        } else {
+            authorize(env.message.sender.clone(), route.to.clone())?;
+        }
+        match input.hops[0].from_token {
+            Token::Snip20(_) => received.push(false),
+            Token::Native(_) => {
+                add_native_input(&mut native_inputs, &input.hops[0], input.amount)?;
+                received.push(true);
+            }
+        }
+    }
+    validate_sent_funds(env, &native_inputs)?;
+
+    if received.iter().all(|received| *received) {
+        return start_multi_input_route(deps, env, env.message.sender.clone(), route);
+    }
+    for input in &route.inputs {
+        if let Token::Native(_) = input.hops[0].from_token {
+            add_escrowed_amount(
+                &mut deps.storage,
+                &escrow_key(&input.hops[0].from_token, &input.hops[0].redeem_denom),
+                input.amount,
+            )?;
+        }
+    }
+    store_multi_input_collection(
         &mut deps.storage,
-        &RouteState {
-            current_hop: first_hop.clone(),
-            remaining_route: Route {
-                hops: hops.clone(), // hops was mutated earlier when we did `hops.pop_front()`
-                estimated_amount,
-                minimum_acceptable_amount,
-                to,
-            },
+        &MultiInputCollection {
+            owner: env.message.sender.clone(),
+            route,
+            received,
         },
     )?;
-    let mut messages = hop_messages(first_hop, amount, env)?;
-    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-        contract_addr: env.contract.address.clone(),
-        callback_code_hash: env.contract_code_hash.clone(),
-        msg: to_binary(&HandleMsg::FinalizeRoute {})?,
-        send: vec![],
-    }));
+
+    Ok(HandleResponse::default())
+}
+
+// Receive one of the sender's SNIP-20 inputs and start the route once every input is received
+fn collect_input<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    from: HumanAddr,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let mut collection: MultiInputCollection =
+        match read_multi_input_collection(&deps.storage, &from)? {
+            Some(collection) => collection,
+            None => {
+                return Err(StdError::generic_err(
+                    "No multi-input route is being collected.",
+                ))
+            }
+        };
+    let index: Option<usize> = (0..collection.route.inputs.len()).find(|index| {
+        let input: &RouteInput = &collection.route.inputs[*index];
+        !collection.received[*index]
+            && input.amount == amount
+            && match input.hops[0].from_token {
+                Token::Snip20(ref token) => token.address == env.message.sender,
+                Token::Native(_) => false,
+            }
+    });
+    match index {
+        Some(index) => collection.received[index] = true,
+        None => {
+            return Err(StdError::generic_err(
+                "Received input isn't part of the multi-input route.",
+            ))
+        }
+    }
+    add_escrowed_amount(&mut deps.storage, &env.message.sender.0, amount)?;
+
+    if collection.received.iter().all(|received| *received) {
+        delete_multi_input_collection(&mut deps.storage, &from);
+        remove_collection_escrow(&mut deps.storage, &collection)?;
+        return start_multi_input_route(deps, env, from, collection.route);
+    }
+    let inputs_remaining: usize = collection
+        .received
+        .iter()
+        .filter(|received| !**received)
+        .count();
+    store_multi_input_collection(&mut deps.storage, &collection)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("inputs_remaining", inputs_remaining.to_string())],
+        data: None,
+    })
+}
+
+fn cancel_multi_input_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<HandleResponse> {
+    let collection: MultiInputCollection =
+        match read_multi_input_collection(&deps.storage, &env.message.sender)? {
+            Some(collection) => collection,
+            None => {
+                return Err(StdError::generic_err(
+                    "No multi-input route is being collected.",
+                ))
+            }
+        };
+    delete_multi_input_collection(&mut deps.storage, &collection.owner);
+    remove_collection_escrow(&mut deps.storage, &collection)?;
+
+    // Refund the inputs received so far
+    let mut messages: Vec<CosmosMsg> = vec![];
+    for (input, received) in collection.route.inputs.iter().zip(collection.received) {
+        if received {
+            messages.push(transfer_token_msg(
+                input.hops[0].from_token.clone(),
+                input.hops[0].redeem_denom.clone(),
+                collection.owner.clone(),
+                input.amount,
+                env,
+            )?);
+        }
+    }
 
     Ok(HandleResponse {
         messages,
@@ -223,46 +887,383 @@ fn handle_first_hop<S: Storage, A: Api, Q: Querier>(
     })
 }
 
-fn handle_hop<S: Storage, A: Api, Q: Querier>(
+fn remove_collection_escrow<S: Storage>(
+    storage: &mut S,
+    collection: &MultiInputCollection,
+) -> StdResult<()> {
+    for (input, received) in collection.route.inputs.iter().zip(&collection.received) {
+        if *received {
+            remove_escrowed_amount(
+                storage,
+                &escrow_key(&input.hops[0].from_token, &input.hops[0].redeem_denom),
+                input.amount,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// The first input is swapped by start_route,
+// the rest are swapped after it and their outputs are summed in handle_hop
+fn start_multi_input_route<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: &Env,
-    from: HumanAddr,
-    mut amount: Uint128,
+    owner: HumanAddr,
+    route: MultiInputRoute,
 ) -> StdResult<HandleResponse> {
     let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
-    let mut logs = vec![];
-    match read_route_state(&deps.storage)? {
-        Some(RouteState {
-            current_hop,
-            remaining_route:
-                Route {
-                    mut hops,
-                    estimated_amount,
-                    minimum_acceptable_amount,
-                    to,
-                },
+    let mut oracle_minimum_amount_sum: Option<Uint128> = None;
+    for input in &route.inputs {
+        validate_whitelisted_hops(&config, &input.hops)?;
+        if let Some(minimum_amount) = oracle_minimum_amount(deps, &input.hops, input.amount)? {
+            oracle_minimum_amount_sum =
+                Some(oracle_minimum_amount_sum.unwrap_or_else(Uint128::zero) + minimum_amount);
+        }
+    }
+    let swap_inputs: Vec<TokenAmount> = route
+        .inputs
+        .iter()
+        .map(|input| TokenAmount {
+            token: token_contract(input.hops[0].from_token.clone()).address,
+            amount: input.amount,
+        })
+        .collect();
+    let swap_hop_count: u32 = route
+        .inputs
+        .iter()
+        .map(|input| hop_count(&input.hops))
+        .sum();
+    let mut inputs: VecDeque<RouteInput> = route.inputs.into_iter().collect();
+    let first_input: RouteInput = inputs.pop_front().unwrap();
+    let mut response: HandleResponse = start_route(
+        deps,
+        env,
+        Route {
+            hops: first_input.hops,
+            estimated_amount: route.estimated_amount,
+            minimum_acceptable_amount: route.minimum_acceptable_amount,
+            to: route.to,
+            allow_third_party_recipient: route.allow_third_party_recipient,
+            zap_in: None,
+            min_profit: None,
+            private: route.private,
+        },
+        first_input.amount,
+        owner,
+        None,
+    )?;
+    let mut route_state: RouteState = read_route_state(&deps.storage)?.unwrap();
+    route_state.remaining_inputs = inputs;
+    route_state.oracle_minimum_amount = oracle_minimum_amount_sum;
+    route_state.swap.inputs = swap_inputs;
+    route_state.swap.hop_count = swap_hop_count;
+    // The other inputs are logged with the same keys as the first
+    for input in &route_state.remaining_inputs {
+        response.log.push(log(
+            "route_input_token",
+            token_contract(input.hops[0].from_token.clone()).address,
+        ));
+        response.log.push(log("route_input_amount", input.amount));
+    }
+    response.log = public_logs(response.log, route_state.remaining_route.private);
+    store_route_state(&mut deps.storage, &route_state)?;
+
+    Ok(response)
+}
+
+// Whitelisted keepers can run arbitrage routes with the contract's own inventory.
+// The output stays in the contract as protocol revenue.
+fn execute_arbitrage<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    amount: Uint128,
+    mut route: Route,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    if !config.keepers.contains(&env.message.sender) {
+        return Err(StdError::Unauthorized { backtrace: None });
+    }
+    if route.min_profit.is_none() {
+        return Err(StdError::generic_err(
+            "Arbitrage route must set min_profit.",
+        ));
+    }
+    if route.hops.len() < 2 {
+        return Err(StdError::generic_err("Route must have at least 2 hops."));
+    }
+    if route.zap_in.is_some() || route.allow_third_party_recipient.unwrap_or(false) {
+        return Err(StdError::generic_err(
+            "Arbitrage route can't zap in or pay a third party.",
+        ));
+    }
+    let input_token: Option<&SecretContract> = match route.hops[0].from_token {
+        Token::Snip20(ref token) => Some(token),
+        Token::Native(_) => None,
+    };
+    validate_unescrowed_amount(deps, env, input_token, &route.hops[0].redeem_denom, amount)?;
+    route.to = env.contract.address.clone();
+
+    start_route(deps, env, route, amount, env.message.sender.clone(), None)
+}
+
+fn start_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    mut route: Route,
+    amount: Uint128,
+    user: HumanAddr,
+    keeper: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    validate_whitelisted_hops(&config, &route.hops)?;
+    if let Some(min_profit) = route.min_profit {
+        validate_arbitrage_route(&route.hops)?;
+        route.minimum_acceptable_amount =
+            std::cmp::max(route.minimum_acceptable_amount, amount + min_profit);
+    }
+    let oracle_minimum_amount: Option<Uint128> = oracle_minimum_amount(deps, &route.hops, amount)?;
+    let swap: Swap = Swap {
+        inputs: vec![TokenAmount {
+            token: token_contract(route.hops[0].from_token.clone()).address,
+            amount,
+        }],
+        output: TokenAmount {
+            token: token_contract(route.hops[route.hops.len() - 1].from_token.clone()).address,
+            amount: Uint128::zero(),
+        },
+        fee_amount: Uint128::zero(),
+        block_height: env.block.height,
+        hop_count: hop_count(&route.hops),
+    };
+    let first_hop: Hop = route.hops.pop_front().unwrap();
+    if route
+        .hops
+        .iter()
+        .any(|hop| hop.protocol == Some(Protocol::WithdrawLiquidity))
+    {
+        return Err(StdError::generic_err(
+            "Withdraw liquidity must be the first hop.",
+        ));
+    }
+    if first_hop.protocol == Some(Protocol::WithdrawLiquidity) {
+        // The pair sends both withdrawn tokens in the pool's order before the zap out continues
+        let pair: SecretContract = first_hop.smart_contract.clone().unwrap();
+        let token_out: SecretContract = match first_hop.token_out.clone() {
+            Some(token_out) => token_out,
+            None => {
+                return Err(StdError::generic_err(
+                    "Withdraw liquidity hop must set token_out.",
+                ))
+            }
+        };
+        let pool: SecretSwapPoolResponse = pair_pool(&deps.querier, &pair)?;
+        pool_asset_index(&pool, &token_out)?;
+        for index in (0..2).rev() {
+            route.hops.push_front(Hop {
+                protocol: Some(Protocol::WithdrawLiquidity),
+                token_out: Some(token_out.clone()),
+                ..swap_hop(Token::Snip20(pool_token(&pool, index)?), Some(pair.clone()))
+            });
+        }
+    }
+
+    let mut logs = vec![
+        log(
+            "route_input_token",
+            token_contract(first_hop.from_token.clone()).address,
+        ),
+        log("route_input_amount", amount),
+    ];
+    logs.extend(hop_logs(0, &first_hop, &route.hops[0].from_token, amount));
+    logs = public_logs(logs, route.private);
+
+    store_route_state(
+        &mut deps.storage,
+        &RouteState {
+            current_hop: first_hop.clone(),
+            hop_index: 1,
+            remaining_route: route,
+            zap_in_retained_amount: None,
+            zap_out_retained_amount: None,
+            balance_before_mint: None,
+            keeper,
+            oracle_minimum_amount,
+            remaining_inputs: VecDeque::new(),
+            collected_amount: None,
+            user,
+            swap,
+        },
+    )?;
+    let mut messages = hop_messages(first_hop, amount, env)?;
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        callback_code_hash: env.contract_code_hash.clone(),
+        msg: to_binary(&HandleMsg::FinalizeRoute {})?,
+        send: vec![],
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+fn handle_hop<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    from: HumanAddr,
+    mut amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let mut logs = vec![];
+    let mut data: Option<Binary> = None;
+    match read_route_state(&deps.storage)? {
+        Some(RouteState {
+            current_hop,
+            mut hop_index,
+            mut remaining_route,
+            mut zap_in_retained_amount,
+            mut zap_out_retained_amount,
+            balance_before_mint,
+            keeper,
+            oracle_minimum_amount,
+            mut remaining_inputs,
+            mut collected_amount,
+            user,
+            mut swap,
         }) => {
-            let next_hop: Hop = match hops.pop_front() {
+            let estimated_amount: Uint128 = remaining_route.estimated_amount;
+            let minimum_acceptable_amount: Uint128 = remaining_route.minimum_acceptable_amount;
+            let to: HumanAddr = remaining_route.to.clone();
+            let private: Option<bool> = remaining_route.private;
+            let next_hop: Hop = match remaining_route.hops.pop_front() {
                 Some(next_hop) => next_hop,
                 None => return Err(StdError::generic_err("Route must be at least 1 hop.")),
             };
-            validate_received_token(next_hop.from_token.clone(), amount, env)?;
+            // The last hop doesn't have to repeat the denom redeemed by the current hop
+            validate_received_token(
+                next_hop.from_token.clone(),
+                next_hop
+                    .redeem_denom
+                    .clone()
+                    .or_else(|| current_hop.redeem_denom.clone()),
+                amount,
+                env,
+            )?;
             validate_received_from_an_allowed_address(
                 current_hop.clone(),
                 next_hop.clone(),
                 env,
                 from,
             )?;
+            validate_hop_minimum_output(&current_hop, amount)?;
+            // The second withdrawn token comes from the same withdraw liquidity hop as the first
+            if next_hop.protocol != Some(Protocol::WithdrawLiquidity)
+                || zap_out_retained_amount.is_none()
+            {
+                record_hop_stats(&mut deps.storage, &current_hop)?;
+            }
 
             let mut messages = vec![];
-            if hops.is_empty() {
+            // the zap in swap through the pair replaces the next hop as the current hop
+            let mut current_hop_to_store: Hop = next_hop.clone();
+            if next_hop.protocol != Some(Protocol::WithdrawLiquidity) {
+                if let Some(retained_amount) = zap_out_retained_amount.take() {
+                    amount += retained_amount;
+                }
+            }
+            if next_hop.protocol == Some(Protocol::WithdrawLiquidity) {
+                match zap_out_retained_amount {
+                    // Wait for the other withdrawn token
+                    None => zap_out_retained_amount = Some(amount),
+                    // Both tokens have been withdrawn, keep token_out and swap the other one into it
+                    Some(first_amount) => {
+                        let (swap_token, swap_amount, retained_amount) =
+                            if token_contract(next_hop.from_token.clone())
+                                == next_hop.token_out.clone().unwrap()
+                            {
+                                (current_hop.from_token.clone(), first_amount, amount)
+                            } else {
+                                (next_hop.from_token.clone(), amount, first_amount)
+                            };
+                        current_hop_to_store =
+                            swap_hop(swap_token, next_hop.smart_contract.clone());
+                        logs.extend(hop_logs(
+                            hop_index,
+                            &current_hop_to_store,
+                            &next_hop.from_token,
+                            swap_amount,
+                        ));
+                        hop_index += 1;
+                        messages = hop_messages(current_hop_to_store.clone(), swap_amount, env)?;
+                        zap_out_retained_amount = Some(retained_amount);
+                    }
+                }
+            } else if let Some(retained_amount) = zap_in_retained_amount {
+                // Half of the route's output was swapped through the pair,
+                // provide liquidity with both halves and send the minted liquidity token to the user
+                messages = zap_in_messages(
+                    &deps.querier,
+                    remaining_route.zap_in.clone().unwrap(),
+                    token_contract(current_hop.from_token.clone()),
+                    retained_amount,
+                    token_contract(next_hop.from_token.clone()),
+                    amount,
+                    to,
+                    env,
+                )?;
+                zap_in_retained_amount = None;
+                // Keep the route's output token as the current hop, the fee was taken in it
+                current_hop_to_store = current_hop.clone();
+            } else if remaining_route.hops.is_empty() && !remaining_inputs.is_empty() {
+                // Keep this input's output and swap the next input of the multi-input route
+                collected_amount = Some(collected_amount.unwrap_or_else(Uint128::zero) + amount);
+                let mut input: RouteInput = remaining_inputs.pop_front().unwrap();
+                current_hop_to_store = input.hops.pop_front().unwrap();
+                logs.extend(hop_logs(
+                    hop_index,
+                    &current_hop_to_store,
+                    &input.hops[0].from_token,
+                    input.amount,
+                ));
+                hop_index += 1;
+                messages = hop_messages(current_hop_to_store.clone(), input.amount, env)?;
+                remaining_route.hops = input.hops;
+            } else if remaining_route.hops.is_empty() {
+                if let Some(collected_amount) = collected_amount.take() {
+                    amount += collected_amount;
+                }
+                if let Some(oracle_minimum_amount) = oracle_minimum_amount {
+                    if amount < oracle_minimum_amount {
+                        return Err(StdError::generic_err(
+                            "Output is worse than the oracle price.",
+                        ));
+                    }
+                }
+                if let Some(keeper) = keeper.clone() {
+                    // Pay the keeper that executed the limit order from the output
+                    let keeper_fee: Uint128 =
+                        amount.multiply_ratio(KEEPER_FEE_BASIS_POINTS, 10_000u128);
+                    messages.push(transfer_token_msg(
+                        next_hop.from_token.clone(),
+                        current_hop.redeem_denom.clone(),
+                        keeper,
+                        keeper_fee,
+                        env,
+                    )?);
+                    amount = (amount - keeper_fee)?;
+                    swap.fee_amount += keeper_fee;
+                }
                 if amount.lt(&minimum_acceptable_amount) {
                     return Err(StdError::generic_err(
                         "Operation fell short of minimum_acceptable_amount",
                     ));
                 }
                 // Send fee to appropriate person
-                if amount > estimated_amount {
+                // (arbitrage run with the contract's inventory keeps everything in the contract)
+                if amount > estimated_amount && to != env.contract.address {
                     let excess: Uint128 = (amount - estimated_amount).unwrap();
                     match next_hop.clone().from_token {
                         Token::Snip20(SecretContract {
@@ -290,1058 +1291,5693 @@ fn handle_hop<S: Storage, A: Api, Q: Querier>(
                         }
                     };
                     amount = estimated_amount;
+                    swap.fee_amount += excess;
                 }
-                // Send amount to user
-                match next_hop.clone().from_token {
-                    Token::Snip20(SecretContract {
-                        address,
-                        contract_hash,
-                    }) => {
-                        messages.push(snip20::send_msg(
-                            to.clone(),
-                            amount,
-                            None,
-                            None,
-                            BLOCK_SIZE,
-                            contract_hash,
+                logs.push(log(
+                    "fee_token",
+                    token_contract(next_hop.from_token.clone()).address,
+                ));
+                logs.push(log("fee_amount", swap.fee_amount));
+                if let Some(zap_in) = remaining_route.zap_in.clone() {
+                    // Swap half of the output through the pair so that liquidity can be provided with both tokens
+                    let token: SecretContract = match next_hop.from_token.clone() {
+                        Token::Snip20(token) => token,
+                        Token::Native(_) => {
+                            return Err(StdError::generic_err(
+                                "Zap in requires the route to end with a SNIP-20.",
+                            ))
+                        }
+                    };
+                    let pool: SecretSwapPoolResponse = pair_pool(&deps.querier, &zap_in.pair)?;
+                    let other_token: SecretContract =
+                        pool_other_token(&pool, pool_asset_index(&pool, &token)?)?;
+                    let swap_amount: Uint128 = amount.multiply_ratio(1u128, 2u128);
+                    current_hop_to_store = swap_hop(Token::Snip20(token), Some(zap_in.pair));
+                    logs.extend(hop_logs(
+                        hop_index,
+                        &current_hop_to_store,
+                        &Token::Snip20(other_token.clone()),
+                        swap_amount,
+                    ));
+                    hop_index += 1;
+                    messages.extend(hop_messages(
+                        current_hop_to_store.clone(),
+                        swap_amount,
+                        env,
+                    )?);
+                    remaining_route
+                        .hops
+                        .push_back(swap_hop(Token::Snip20(other_token), None));
+                    zap_in_retained_amount = Some((amount - swap_amount)?);
+                } else if to == env.contract.address {
+                    // Profit from arbitrage run by a keeper is kept as protocol revenue
+                    swap.output = TokenAmount {
+                        token: token_contract(next_hop.from_token.clone()).address,
+                        amount,
+                    };
+                    logs.extend(payout_logs(&to, &swap.output));
+                    data = Some(swap_result(&swap, hop_index)?);
+                    record_route_stats(&mut deps.storage, &swap, swap.output.token.clone())?;
+                } else {
+                    // Send amount to user
+                    match next_hop.clone().from_token {
+                        Token::Snip20(SecretContract {
                             address,
-                        )?);
-                    }
-                    Token::Native(_) => {
-                        messages.push(CosmosMsg::Bank(BankMsg::Send {
-                            from_address: env.contract.address.clone(),
-                            to_address: to.clone(),
-                            amount: vec![Coin {
+                            contract_hash,
+                        }) => {
+                            messages.push(snip20::send_msg(
+                                to.clone(),
                                 amount,
-                                denom: current_hop.redeem_denom.unwrap(),
-                            }],
-                        }));
-                    }
-                };
-                logs = vec![log("return_amount", amount.to_string())];
+                                None,
+                                None,
+                                BLOCK_SIZE,
+                                contract_hash,
+                                address,
+                            )?);
+                        }
+                        Token::Native(_) => {
+                            messages.push(CosmosMsg::Bank(BankMsg::Send {
+                                from_address: env.contract.address.clone(),
+                                to_address: to.clone(),
+                                amount: vec![Coin {
+                                    amount,
+                                    denom: current_hop.redeem_denom.unwrap(),
+                                }],
+                            }));
+                        }
+                    };
+                    swap.output = TokenAmount {
+                        token: token_contract(next_hop.from_token.clone()).address,
+                        amount,
+                    };
+                    logs.extend(payout_logs(&to, &swap.output));
+                    data = Some(swap_result(&swap, hop_index)?);
+                    append_swap(&mut deps.storage, &user, &swap)?;
+                    record_route_stats(&mut deps.storage, &swap, swap.output.token.clone())?;
+                }
             } else {
+                logs.extend(hop_logs(
+                    hop_index,
+                    &next_hop,
+                    &remaining_route.hops[0].from_token,
+                    amount,
+                ));
+                hop_index += 1;
                 messages = hop_messages(next_hop.clone(), amount, env)?;
             }
             store_route_state(
                 &mut deps.storage,
                 &RouteState {
-                    current_hop: next_hop,
-                    remaining_route: Route {
-                        hops,
-                        estimated_amount,
-                        minimum_acceptable_amount,
-                        to,
-                    },
+                    current_hop: current_hop_to_store,
+                    hop_index,
+                    remaining_route,
+                    zap_in_retained_amount,
+                    zap_out_retained_amount,
+                    balance_before_mint,
+                    keeper,
+                    oracle_minimum_amount,
+                    remaining_inputs,
+                    collected_amount,
+                    user,
+                    swap,
                 },
             )?;
 
             Ok(HandleResponse {
                 messages,
-                log: logs,
-                data: None,
+                log: public_logs(logs, private),
+                data,
             })
         }
         None => Err(StdError::generic_err("cannot find route")),
     }
 }
 
-fn finalize_route<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: &Env,
-) -> StdResult<HandleResponse> {
-    match read_route_state(&deps.storage)? {
-        Some(RouteState {
-            remaining_route, ..
-        }) => {
-            // this function is called only by the route creation function
-            // it is intended to always make sure that the route was completed successfully
-            // otherwise we revert the transaction
-            authorize(env.contract.address.clone(), env.message.sender.clone())?;
-            if !remaining_route.hops.is_empty() {
-                return Err(StdError::generic_err(format!(
-                    "cannot finalize: route still contains hops: {:?}",
-                    remaining_route
-                )));
+// Every hop that is sent is logged with the same keys
+fn hop_logs(hop_index: u32, hop: &Hop, token_out: &Token, amount_in: Uint128) -> Vec<LogAttribute> {
+    vec![
+        log("hop_index", hop_index),
+        log("hop_kind", hop_kind(hop)),
+        log(
+            "hop_token_in",
+            token_contract(hop.from_token.clone()).address,
+        ),
+        log("hop_token_out", token_contract(token_out.clone()).address),
+        log("hop_amount_in", amount_in),
+    ]
+}
+
+// Follows the order in which hop_messages picks how to send the hop
+fn hop_kind(hop: &Hop) -> &'static str {
+    match hop.from_token {
+        Token::Snip20(_) => {
+            if hop.sienna_swap_router_path.is_some() {
+                "sienna_swap_router"
+            } else if hop.shade_protocol_router_path.is_some() {
+                "shade_protocol_router"
+            } else if hop.migrate_to_token.is_some() {
+                "migration"
+            } else if hop.protocol == Some(Protocol::WithdrawLiquidity) {
+                "withdraw_liquidity"
+            } else if hop.protocol == Some(Protocol::StakingDerivative) {
+                "staking_derivative"
+            } else if hop.redeem_denom.is_some() {
+                "redeem"
+            } else {
+                match hop.protocol {
+                    Some(Protocol::ShadeSwap) => "shade_swap",
+                    Some(Protocol::SiennaSwap) => "sienna_swap",
+                    _ => "secret_swap",
+                }
             }
-            delete_route_state(&mut deps.storage);
-            Ok(HandleResponse::default())
         }
-        None => Err(StdError::generic_err("no route to finalize")),
+        Token::Native(_) if hop.protocol == Some(Protocol::StakingDerivative) => {
+            "staking_derivative"
+        }
+        Token::Native(_) => "deposit",
     }
 }
 
-fn register_tokens(env: &Env, tokens: Vec<SecretContract>) -> StdResult<HandleResponse> {
-    let mut messages = vec![];
-    for token in tokens {
-        let address = token.address;
-        let contract_hash = token.contract_hash;
-        messages.push(snip20::register_receive_msg(
-            env.contract_code_hash.clone(),
-            None,
-            BLOCK_SIZE,
-            contract_hash.clone(),
-            address.clone(),
-        )?);
+// Private routes leave out every log that carries an amount
+fn public_logs(mut logs: Vec<LogAttribute>, private: Option<bool>) -> Vec<LogAttribute> {
+    if private.unwrap_or(false) {
+        logs.retain(|log| !AMOUNT_LOG_KEYS.contains(&log.key.as_str()));
     }
 
-    Ok(HandleResponse {
-        messages,
-        log: vec![],
-        data: None,
+    logs
+}
+
+fn payout_logs(recipient: &HumanAddr, output: &TokenAmount) -> Vec<LogAttribute> {
+    vec![
+        log("payout_recipient", recipient),
+        log("payout_token", &output.token),
+        log("return_amount", output.amount),
+    ]
+}
+
+fn swap_result(swap: &Swap, hops_executed: u32) -> StdResult<Binary> {
+    to_binary(&SwapResult {
+        return_amount: swap.output.amount,
+        fee_amount: swap.fee_amount,
+        output_token: swap.output.token.clone(),
+        hops_executed,
     })
 }
 
-fn rescue_tokens<S: Storage, A: Api, Q: Querier>(
-    deps: &mut Extern<S, A, Q>,
-    env: &Env,
-    amount: Uint128,
-    denom: Option<String>,
-    token: Option<SecretContract>,
-) -> StdResult<HandleResponse> {
-    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
-    authorize(config.admin.clone(), env.message.sender.clone())?;
+fn record_hop_stats<S: Storage>(storage: &mut S, hop: &Hop) -> StdResult<()> {
+    let hop_kind: &str = hop_kind(hop);
+    let hop_count: u64 = read_hop_count(storage, hop_kind)?;
+    store_hop_count(storage, hop_kind, hop_count + 1)
+}
 
-    let mut messages: Vec<CosmosMsg> = vec![];
-    if let Some(denom_unwrapped) = denom {
-        let withdrawal_coin: Vec<Coin> = vec![Coin {
-            amount,
-            denom: denom_unwrapped,
-        }];
-        messages.push(CosmosMsg::Bank(BankMsg::Send {
-            from_address: env.contract.address.clone(),
-            to_address: config.admin.clone(),
-            amount: withdrawal_coin,
-        }));
+// Fees are taken from the route's output before any zap in, so fee_token is that output token
+fn record_route_stats<S: Storage>(
+    storage: &mut S,
+    swap: &Swap,
+    fee_token: HumanAddr,
+) -> StdResult<()> {
+    let route_count: u64 = read_route_count(storage)?;
+    store_route_count(storage, route_count + 1)?;
+    for input in &swap.inputs {
+        let mut token_stats: TokenStats = read_token_stats(storage, &input.token)?;
+        token_stats.input_volume += input.amount;
+        store_token_stats(storage, &input.token, &token_stats)?;
     }
-
-    if let Some(token_unwrapped) = token {
-        messages.push(snip20::transfer_msg(
-            config.admin,
-            amount,
-            None,
-            BLOCK_SIZE,
-            token_unwrapped.contract_hash,
-            token_unwrapped.address,
-        )?)
+    let mut token_stats: TokenStats = read_token_stats(storage, &swap.output.token)?;
+    token_stats.output_volume += swap.output.amount;
+    store_token_stats(storage, &swap.output.token, &token_stats)?;
+    if !swap.fee_amount.is_zero() {
+        let mut token_stats: TokenStats = read_token_stats(storage, &fee_token)?;
+        token_stats.fees += swap.fee_amount;
+        store_token_stats(storage, &fee_token, &token_stats)?;
     }
 
-    Ok(HandleResponse {
-        messages,
-        log: vec![],
-        data: None,
-    })
+    Ok(())
 }
 
-pub fn query<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-    msg: QueryMsg,
-) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Config {} => {
-            let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
-            Ok(to_binary(&config)?)
-        }
+fn token_contract(token: Token) -> SecretContract {
+    match token {
+        Token::Snip20(secret_contract) => secret_contract,
+        Token::Native(secret_contract) => secret_contract,
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::state::SecretContractForShadeProtocol;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage};
-    use std::collections::VecDeque;
+// The last hop of a route is the output token
+fn hop_count(hops: &VecDeque<Hop>) -> u32 {
+    hops.len().saturating_sub(1) as u32
+}
 
-    // === HELPERS ===
-    fn init_helper() -> (
-        StdResult<InitResponse>,
-        Extern<MockStorage, MockApi, MockQuerier>,
-    ) {
-        let env = mock_env(mock_contract_initiator_address(), &[]);
-        let mut deps = mock_dependencies(20, &[]);
-        let msg = InitMsg {};
-        (init(&mut deps, env, msg), deps)
+// Fail at the hop that returned less than its minimum_output.
+// Withdrawn liquidity arrives in two parts so it isn't checked here.
+fn validate_hop_minimum_output(hop: &Hop, amount: Uint128) -> StdResult<()> {
+    if hop.protocol == Some(Protocol::WithdrawLiquidity) {
+        return Ok(());
     }
-
-    fn mock_button() -> SecretContract {
-        SecretContract {
-            address: HumanAddr::from("mock-button-address"),
-            contract_hash: "mock-button-contract-hash".to_string(),
+    if let Some(minimum_output) = hop.minimum_output {
+        if amount < minimum_output {
+            let hop_address: HumanAddr = match hop.smart_contract {
+                Some(ref smart_contract) => smart_contract.address.clone(),
+                None => token_contract(hop.from_token.clone()).address,
+            };
+            return Err(StdError::generic_err(format!(
+                "Hop through {} returned {} which is less than its minimum_output of {}.",
+                hop_address, amount, minimum_output
+            )));
         }
     }
 
-    fn mock_contract() -> SecretContract {
-        let env = mock_env(mock_user_address(), &[]);
-        SecretContract {
-            address: env.contract.address,
-            contract_hash: env.contract_code_hash,
-        }
-    }
+    Ok(())
+}
 
-    fn mock_contract_initiator_address() -> HumanAddr {
-        HumanAddr::from("btn.group")
+// A plain swap through smart_contract, or the output token when smart_contract is None
+fn swap_hop(from_token: Token, smart_contract: Option<SecretContract>) -> Hop {
+    Hop {
+        from_token,
+        smart_contract,
+        redeem_denom: None,
+        migrate_to_token: None,
+        shade_protocol_router_path: None,
+        protocol: None,
+        sienna_swap_router_path: None,
+        token_out: None,
+        minimum_output: None,
     }
+}
 
-    fn mock_denom() -> String {
-        "uatom".to_string()
-    }
+fn pair_pool<Q: Querier>(querier: &Q, pair: &SecretContract) -> StdResult<SecretSwapPoolResponse> {
+    SecretSwapPairQuery::Pool {}.query(querier, pair.contract_hash.clone(), pair.address.clone())
+}
 
-    fn mock_pair_contract() -> SecretContract {
-        SecretContract {
-            address: HumanAddr::from("pair-contract-address"),
-            contract_hash: "pair-contract-contract-hash".to_string(),
-        }
+fn pool_asset_index(pool: &SecretSwapPoolResponse, token: &SecretContract) -> StdResult<usize> {
+    pool.assets
+        .iter()
+        .position(|asset| match asset.info {
+            AssetInfo::Token {
+                ref contract_addr, ..
+            } => *contract_addr == token.address,
+            AssetInfo::NativeToken { .. } => false,
+        })
+        .ok_or_else(|| StdError::generic_err("Token is not in the pair."))
+}
+
+fn pool_other_token(pool: &SecretSwapPoolResponse, index: usize) -> StdResult<SecretContract> {
+    pool_token(pool, 1 - index)
+}
+
+fn pool_token(pool: &SecretSwapPoolResponse, index: usize) -> StdResult<SecretContract> {
+    match pool.assets[index].info {
+        AssetInfo::Token {
+            ref contract_addr,
+            ref token_code_hash,
+            ..
+        } => Ok(SecretContract {
+            address: contract_addr.clone(),
+            contract_hash: token_code_hash.clone(),
+        }),
+        AssetInfo::NativeToken { .. } => Err(StdError::generic_err(
+            "Pairs with a native token are not supported.",
+        )),
     }
+}
 
-    fn mock_pair_contract_two() -> SecretContract {
-        SecretContract {
-            address: HumanAddr::from("pair-contract-two-address"),
-            contract_hash: "pair-contract-two-hash".to_string(),
+// amount * numerator / denominator with a 256 bit product, as multiply_ratio overflows u128
+// for 18 decimal amounts multiplied by reserves or rates
+fn wide_multiply_ratio(amount: Uint128, numerator: u128, denominator: u128) -> StdResult<Uint128> {
+    if denominator == 0 {
+        return Err(StdError::generic_err("Can't divide by zero."));
+    }
+    const LOW_BITS: u128 = u64::MAX as u128;
+    let (a, b): (u128, u128) = (amount.u128(), numerator);
+    let (a_high, a_low, b_high, b_low) = (a >> 64, a & LOW_BITS, b >> 64, b & LOW_BITS);
+    let low_low: u128 = a_low * b_low;
+    let low_high: u128 = a_low * b_high;
+    let high_low: u128 = a_high * b_low;
+    let middle: u128 = (low_low >> 64) + (low_high & LOW_BITS) + (high_low & LOW_BITS);
+    let high: u128 = a_high * b_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+    let low: u128 = (low_low & LOW_BITS) | (middle << 64);
+    if high >= denominator {
+        return Err(StdError::generic_err("Amount is too large."));
+    }
+    // Long division of the 256 bit product, the remainder stays below the denominator
+    let mut remainder: u128 = high;
+    let mut quotient: u128 = 0;
+    for i in (0..128).rev() {
+        let carry: bool = remainder >> 127 == 1;
+        remainder = (remainder << 1) | ((low >> i) & 1);
+        if carry || remainder >= denominator {
+            remainder = remainder.wrapping_sub(denominator);
+            quotient |= 1 << i;
         }
     }
 
-    fn mock_shade_protocol_router() -> SecretContract {
-        SecretContract {
-            address: HumanAddr::from("mock-shade-protocol-router-address"),
-            contract_hash: "mock-shade-protocol-router-contract-hash".to_string(),
-        }
+    Ok(Uint128(quotient))
+}
+
+// Provide liquidity with the amounts that match the pool's ratio, send the minted liquidity token to the user
+// and refund the rest.
+// The pair mints the liquidity token without calling Receive, so the minted amount is the increase
+// in this contract's balance.
+#[allow(clippy::too_many_arguments)]
+fn zap_in_messages<Q: Querier>(
+    querier: &Q,
+    zap_in: ZapIn,
+    token_a: SecretContract,
+    amount_a: Uint128,
+    token_b: SecretContract,
+    amount_b: Uint128,
+    to: HumanAddr,
+    env: &Env,
+) -> StdResult<Vec<CosmosMsg>> {
+    let pool: SecretSwapPoolResponse = pair_pool(querier, &zap_in.pair)?;
+    let index_a: usize = pool_asset_index(&pool, &token_a)?;
+    let index_b: usize = pool_asset_index(&pool, &token_b)?;
+    let reserve_a: Uint128 = pool.assets[index_a].amount;
+    let reserve_b: Uint128 = pool.assets[index_b].amount;
+    if reserve_a.is_zero() || reserve_b.is_zero() || pool.total_share.is_zero() {
+        return Err(StdError::generic_err("Zap in pair has no liquidity."));
     }
+    let mut deposit_a: Uint128 = amount_a;
+    let mut deposit_b: Uint128 = wide_multiply_ratio(amount_a, reserve_b.u128(), reserve_a.u128())?;
+    if deposit_b > amount_b {
+        deposit_b = amount_b;
+        deposit_a = wide_multiply_ratio(amount_b, reserve_a.u128(), reserve_b.u128())?;
+    }
+    let mut assets: [Asset; 2] = pool.assets.clone();
+    assets[index_a].amount = deposit_a;
+    assets[index_b].amount = deposit_b;
 
-    fn mock_sscrt() -> SecretContract {
-        SecretContract {
-            address: HumanAddr::from("mock-sscrt-address"),
-            contract_hash: "mock-sscrt-contract-hash".to_string(),
+    let mut messages: Vec<CosmosMsg> = vec![
+        snip20::increase_allowance_msg(
+            zap_in.pair.address.clone(),
+            deposit_a,
+            None,
+            None,
+            BLOCK_SIZE,
+            token_a.contract_hash.clone(),
+            token_a.address.clone(),
+        )?,
+        snip20::increase_allowance_msg(
+            zap_in.pair.address.clone(),
+            deposit_b,
+            None,
+            None,
+            BLOCK_SIZE,
+            token_b.contract_hash.clone(),
+            token_b.address.clone(),
+        )?,
+        self_msg(
+            env,
+            &HandleMsg::RecordBalance {
+                token: zap_in.liquidity_token.clone(),
+            },
+        )?,
+        SecretSwapPair::ProvideLiquidity {
+            assets,
+            slippage_tolerance: None,
+        }
+        .to_cosmos_msg(zap_in.pair.contract_hash, zap_in.pair.address, None)?,
+        self_msg(
+            env,
+            &HandleMsg::SendMintedBalance {
+                token: zap_in.liquidity_token,
+            },
+        )?,
+    ];
+    for (token, amount, deposit) in [
+        (token_a, amount_a, deposit_a),
+        (token_b, amount_b, deposit_b),
+    ] {
+        let refund: Uint128 = (amount - deposit)?;
+        if !refund.is_zero() {
+            messages.push(snip20::send_msg(
+                to.clone(),
+                refund,
+                None,
+                None,
+                BLOCK_SIZE,
+                token.contract_hash,
+                token.address,
+            )?);
         }
     }
 
-    fn mock_token() -> SecretContract {
-        SecretContract {
-            address: HumanAddr::from("mock-token-address"),
-            contract_hash: "mock-token-contract-hash".to_string(),
+    Ok(messages)
+}
+
+fn transfer_token_msg(
+    token: Token,
+    denom: Option<String>,
+    recipient: HumanAddr,
+    amount: Uint128,
+    env: &Env,
+) -> StdResult<CosmosMsg> {
+    match token {
+        Token::Snip20(SecretContract {
+            address,
+            contract_hash,
+        }) => snip20::transfer_msg(recipient, amount, None, BLOCK_SIZE, contract_hash, address),
+        Token::Native(_) => Ok(CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address.clone(),
+            to_address: recipient,
+            amount: vec![Coin {
+                amount,
+                denom: denom.unwrap(),
+            }],
+        })),
+    }
+}
+
+// Validate the input of a stored order and return its denom if it's native
+fn validate_escrow(
+    env: &Env,
+    from: HumanAddr,
+    amount: Uint128,
+    route_template: &Route,
+) -> StdResult<Option<String>> {
+    if route_template.hops.len() < 2 {
+        return Err(StdError::generic_err("Route must have at least 2 hops."));
+    }
+    let input_token: Token = route_template.hops[0].from_token.clone();
+    let denom: Option<String> = route_template.hops[0].redeem_denom.clone();
+    validate_received_token(input_token.clone(), denom.clone(), amount, env)?;
+    validate_user_is_the_receiver(
+        input_token.clone(),
+        from,
+        route_template.to.clone(),
+        env.message.sender.clone(),
+    )?;
+
+    Ok(match input_token {
+        Token::Snip20(_) => None,
+        Token::Native(_) => denom,
+    })
+}
+
+// The order's id and denom are set here
+fn place_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    from: HumanAddr,
+    mut order: LimitOrder,
+) -> StdResult<HandleResponse> {
+    order.denom = validate_escrow(env, from, order.amount, &order.route_template)?;
+    order.id = next_order_id(&mut deps.storage)?;
+    add_escrowed_amount(
+        &mut deps.storage,
+        &escrow_key(&order.route_template.hops[0].from_token, &order.denom),
+        order.amount,
+    )?;
+    store_order(&mut deps.storage, &order)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("order_id", order.id.to_string())],
+        data: None,
+    })
+}
+
+// Any keeper can execute an order with a route that starts and ends with the order's tokens.
+// The escrowed input is sent along the route and the output, less the keeper's fee,
+// must reach min_output.
+fn execute_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    id: u64,
+    route: Route,
+) -> StdResult<HandleResponse> {
+    let order: LimitOrder = match read_order(&deps.storage, id)? {
+        Some(order) => order,
+        None => return Err(StdError::generic_err("Order not found.")),
+    };
+    if let Some(expiration) = order.expiration {
+        if env.block.time >= expiration {
+            return Err(StdError::generic_err("Order has expired."));
         }
     }
+    if let Some(trigger) = order.trigger.clone() {
+        validate_trigger(&deps.querier, trigger)?;
+    }
+    if route.hops.len() < 2 {
+        return Err(StdError::generic_err("Route must have at least 2 hops."));
+    }
+    validate_route_matches_template(&route.hops, &order.route_template.hops)?;
+    delete_order(&mut deps.storage, &order)?;
+    remove_escrowed_amount(
+        &mut deps.storage,
+        &escrow_key(&order.route_template.hops[0].from_token, &order.denom),
+        order.amount,
+    )?;
 
-    fn mock_token_native() -> Token {
-        Token::Native(mock_sscrt())
+    start_route(
+        deps,
+        env,
+        Route {
+            hops: route.hops,
+            estimated_amount: order.route_template.estimated_amount,
+            minimum_acceptable_amount: order.min_output,
+            to: order.owner.clone(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: None,
+            private: order.route_template.private,
+        },
+        order.amount,
+        order.owner,
+        Some(env.message.sender.clone()),
+    )
+}
+
+fn validate_trigger<Q: Querier>(querier: &Q, trigger: Trigger) -> StdResult<()> {
+    let reference_data: OracleReferenceData = OracleQuery::GetReferenceData {
+        base_symbol: trigger.base_symbol,
+        quote_symbol: trigger.quote_symbol,
+    }
+    .query(
+        querier,
+        trigger.oracle.contract_hash,
+        trigger.oracle.address,
+    )?;
+    let triggered: bool = match trigger.kind {
+        TriggerKind::StopLoss => reference_data.rate <= trigger.rate,
+        TriggerKind::TakeProfit => reference_data.rate >= trigger.rate,
+    };
+    if !triggered {
+        return Err(StdError::generic_err("Order hasn't been triggered."));
     }
 
-    fn mock_token_snip20() -> Token {
-        Token::Snip20(mock_sscrt())
+    Ok(())
+}
+
+fn cancel_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    id: u64,
+) -> StdResult<HandleResponse> {
+    let order: LimitOrder = match read_order(&deps.storage, id)? {
+        Some(order) => order,
+        None => return Err(StdError::generic_err("Order not found.")),
+    };
+    authorize(order.owner.clone(), env.message.sender.clone())?;
+    delete_order(&mut deps.storage, &order)?;
+    remove_escrowed_amount(
+        &mut deps.storage,
+        &escrow_key(&order.route_template.hops[0].from_token, &order.denom),
+        order.amount,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![transfer_token_msg(
+            order.route_template.hops[0].from_token.clone(),
+            order.denom,
+            order.owner,
+            order.amount,
+            env,
+        )?],
+        log: vec![],
+        data: None,
+    })
+}
+
+fn create_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    from: HumanAddr,
+    budget: Uint128,
+    route_template: Route,
+    schedule: DcaSchedule,
+) -> StdResult<HandleResponse> {
+    let denom: Option<String> = validate_escrow(env, from, budget, &route_template)?;
+    if schedule.amount_per_period.is_zero() {
+        return Err(StdError::generic_err(
+            "amount_per_period must be greater than zero.",
+        ));
     }
+    let period: u64 = match schedule.period {
+        DcaPeriod::Blocks(blocks) => blocks,
+        DcaPeriod::Seconds(seconds) => seconds,
+    };
+    if period == 0 {
+        return Err(StdError::generic_err("period must be greater than zero."));
+    }
+    let id: u64 = next_dca_id(&mut deps.storage)?;
+    add_escrowed_amount(
+        &mut deps.storage,
+        &escrow_key(&route_template.hops[0].from_token, &denom),
+        budget,
+    )?;
+    store_dca(
+        &mut deps.storage,
+        &Dca {
+            id,
+            owner: route_template.to.clone(),
+            route_template,
+            budget,
+            denom,
+            next_execution: match schedule.period {
+                DcaPeriod::Blocks(_) => env.block.height,
+                DcaPeriod::Seconds(_) => env.block.time,
+            },
+            schedule,
+        },
+    )?;
 
-    fn mock_user_address() -> HumanAddr {
-        HumanAddr::from("gary")
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("dca_id", id.to_string())],
+        data: None,
+    })
+}
+
+// Any keeper can swap the next period's amount once the period has passed.
+// The schedule is removed when the budget runs out.
+fn execute_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    id: u64,
+    route: Route,
+) -> StdResult<HandleResponse> {
+    let mut dca: Dca = match read_dca(&deps.storage, id)? {
+        Some(dca) => dca,
+        None => return Err(StdError::generic_err("DCA not found.")),
+    };
+    let (now, period): (u64, u64) = match dca.schedule.period {
+        DcaPeriod::Blocks(blocks) => (env.block.height, blocks),
+        DcaPeriod::Seconds(seconds) => (env.block.time, seconds),
+    };
+    if now < dca.next_execution {
+        return Err(StdError::generic_err("DCA period hasn't passed yet."));
+    }
+    if route.hops.len() < 2 {
+        return Err(StdError::generic_err("Route must have at least 2 hops."));
+    }
+    validate_route_matches_template(&route.hops, &dca.route_template.hops)?;
+    let amount: Uint128 = std::cmp::min(dca.schedule.amount_per_period, dca.budget);
+    // Only the last period can be smaller, its minimum is scaled down
+    let minimum_acceptable_amount: Uint128 = if amount < dca.schedule.amount_per_period {
+        wide_multiply_ratio(
+            dca.schedule.min_output_per_period,
+            amount.u128(),
+            dca.schedule.amount_per_period.u128(),
+        )?
+    } else {
+        dca.schedule.min_output_per_period
+    };
+    dca.budget = (dca.budget - amount)?;
+    dca.next_execution = now + period;
+    remove_escrowed_amount(
+        &mut deps.storage,
+        &escrow_key(&dca.route_template.hops[0].from_token, &dca.denom),
+        amount,
+    )?;
+    if dca.budget.is_zero() {
+        delete_dca(&mut deps.storage, &dca)?;
+    } else {
+        store_dca(&mut deps.storage, &dca)?;
     }
 
-    // === QUERY TESTS ===
-    #[test]
-    fn test_query_config() {
-        let (_init_result, deps) = init_helper();
-        let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
-        let query_result = query(&deps, QueryMsg::Config {}).unwrap();
-        let query_answer_config: Config = from_binary(&query_result).unwrap();
-        assert_eq!(query_answer_config, config);
+    start_route(
+        deps,
+        env,
+        Route {
+            hops: route.hops,
+            estimated_amount: dca.route_template.estimated_amount,
+            minimum_acceptable_amount,
+            to: dca.owner.clone(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: None,
+            private: dca.route_template.private,
+        },
+        amount,
+        dca.owner,
+        Some(env.message.sender.clone()),
+    )
+}
+
+fn cancel_dca<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    id: u64,
+) -> StdResult<HandleResponse> {
+    let dca: Dca = match read_dca(&deps.storage, id)? {
+        Some(dca) => dca,
+        None => return Err(StdError::generic_err("DCA not found.")),
+    };
+    authorize(dca.owner.clone(), env.message.sender.clone())?;
+    delete_dca(&mut deps.storage, &dca)?;
+    remove_escrowed_amount(
+        &mut deps.storage,
+        &escrow_key(&dca.route_template.hops[0].from_token, &dca.denom),
+        dca.budget,
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![transfer_token_msg(
+            dca.route_template.hops[0].from_token.clone(),
+            dca.denom,
+            dca.owner,
+            dca.budget,
+            env,
+        )?],
+        log: vec![],
+        data: None,
+    })
+}
+
+fn finalize_route<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<HandleResponse> {
+    match read_route_state(&deps.storage)? {
+        Some(RouteState {
+            remaining_route, ..
+        }) => {
+            // this function is called only by the route creation function
+            // it is intended to always make sure that the route was completed successfully
+            // otherwise we revert the transaction
+            authorize(env.contract.address.clone(), env.message.sender.clone())?;
+            if !remaining_route.hops.is_empty() {
+                return Err(StdError::generic_err(format!(
+                    "cannot finalize: route still contains hops: {:?}",
+                    remaining_route
+                )));
+            }
+            delete_route_state(&mut deps.storage);
+            Ok(HandleResponse::default())
+        }
+        None => Err(StdError::generic_err("no route to finalize")),
     }
+}
 
-    // === HANDLE TESTS ===
-    #[test]
-    fn test_finalize_route() {
-        let (_init_result, mut deps) = init_helper();
-        let env = mock_env(mock_user_address(), &[]);
+fn refresh_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    tokens: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    authorize(config.admin, env.message.sender.clone())?;
+    for address in tokens {
+        let metadata: TokenMetadata = match read_token_metadata(&deps.storage, &address)? {
+            Some(metadata) => metadata,
+            None => return Err(StdError::generic_err("Token isn't registered.")),
+        };
+        store_token_info(deps, metadata.token)?;
+    }
 
-        // when route state does not exist
-        // * it raises an error
-        let handle_msg = HandleMsg::FinalizeRoute {};
-        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
-        assert_eq!(
-            handle_result.unwrap_err(),
-            StdError::generic_err("no route to finalize")
-        );
+    Ok(HandleResponse::default())
+}
 
-        // when route state exists
-        // = when there are hops
-        let mut hops: VecDeque<Hop> = VecDeque::new();
-        hops.push_back(Hop {
-            from_token: mock_token_native(),
-            redeem_denom: None,
-            smart_contract: Some(mock_pair_contract()),
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
-        let route_state: RouteState = RouteState {
-            current_hop: Hop {
-                from_token: mock_token_native(),
-                redeem_denom: None,
-                smart_contract: Some(mock_pair_contract()),
-                migrate_to_token: None,
-                shade_protocol_router_path: None,
+fn register_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    tokens: Vec<SecretContract>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    // Anyone can register receive, but only the admin's tokens are listed
+    let store_metadata: bool = env.message.sender == config.admin;
+    let contract_viewing_key: String = read_contract_viewing_key(&deps.storage)?;
+    let mut messages = vec![];
+    for token in tokens {
+        if store_metadata {
+            store_token_info(deps, token.clone())?;
+        }
+        let address = token.address;
+        let contract_hash = token.contract_hash;
+        messages.push(snip20::register_receive_msg(
+            env.contract_code_hash.clone(),
+            None,
+            BLOCK_SIZE,
+            contract_hash.clone(),
+            address.clone(),
+        )?);
+        messages.push(snip20::set_viewing_key_msg(
+            contract_viewing_key.clone(),
+            None,
+            BLOCK_SIZE,
+            contract_hash.clone(),
+            address.clone(),
+        )?);
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: None,
+    })
+}
+
+fn store_token_info<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    token: SecretContract,
+) -> StdResult<()> {
+    // Tokens that can't answer token_info are skipped instead of failing the batch
+    let token_info: snip20::TokenInfo = match snip20::token_info_query(
+        &deps.querier,
+        BLOCK_SIZE,
+        token.contract_hash.clone(),
+        token.address.clone(),
+    ) {
+        Ok(token_info) => token_info,
+        Err(_) => return Ok(()),
+    };
+    store_token_metadata(
+        &mut deps.storage,
+        &TokenMetadata {
+            token,
+            name: token_info.name,
+            symbol: token_info.symbol,
+            decimals: token_info.decimals,
+        },
+    )
+}
+
+fn rescue_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    amount: Uint128,
+    denom: Option<String>,
+    token: Option<SecretContract>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
+    authorize(config.admin.clone(), env.message.sender.clone())?;
+
+    let mut messages: Vec<CosmosMsg> = vec![];
+    if let Some(denom_unwrapped) = denom {
+        validate_unescrowed_amount(deps, env, None, &Some(denom_unwrapped.clone()), amount)?;
+        let withdrawal_coin: Vec<Coin> = vec![Coin {
+            amount,
+            denom: denom_unwrapped,
+        }];
+        messages.push(CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address.clone(),
+            to_address: config.admin.clone(),
+            amount: withdrawal_coin,
+        }));
+    }
+
+    if let Some(token_unwrapped) = token {
+        validate_unescrowed_amount(deps, env, Some(&token_unwrapped), &None, amount)?;
+        messages.push(snip20::transfer_msg(
+            config.admin,
+            amount,
+            None,
+            BLOCK_SIZE,
+            token_unwrapped.contract_hash,
+            token_unwrapped.address,
+        )?)
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![],
+        data: None,
+    })
+}
+
+fn set_keepers<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    keepers: Vec<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let mut config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    authorize(config.admin.clone(), env.message.sender.clone())?;
+    config.keepers = keepers;
+    TypedStoreMut::attach(&mut deps.storage).store(CONFIG_KEY, &config)?;
+
+    Ok(HandleResponse::default())
+}
+
+fn set_migrations<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    migrations: Vec<Migration>,
+) -> StdResult<HandleResponse> {
+    let mut config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    authorize(config.admin.clone(), env.message.sender.clone())?;
+    config.migrations = migrations;
+    TypedStoreMut::attach(&mut deps.storage).store(CONFIG_KEY, &config)?;
+
+    Ok(HandleResponse::default())
+}
+
+fn set_pair_oracle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    input_token: HumanAddr,
+    output_token: HumanAddr,
+    pair_oracle: Option<PairOracle>,
+) -> StdResult<HandleResponse> {
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    authorize(config.admin, env.message.sender.clone())?;
+    if let Some(pair_oracle) = &pair_oracle {
+        if pair_oracle.input_decimals > MAX_ORACLE_DECIMALS
+            || pair_oracle.output_decimals > MAX_ORACLE_DECIMALS
+        {
+            return Err(StdError::generic_err(format!(
+                "Pair oracle decimals can't be more than {}.",
+                MAX_ORACLE_DECIMALS
+            )));
+        }
+    }
+    store_pair_oracle(&mut deps.storage, &input_token, &output_token, pair_oracle)?;
+
+    Ok(HandleResponse::default())
+}
+
+fn set_wrapped_tokens<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    wrapped_tokens: Vec<WrappedToken>,
+) -> StdResult<HandleResponse> {
+    let mut config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    authorize(config.admin.clone(), env.message.sender.clone())?;
+    config.wrapped_tokens = wrapped_tokens;
+    TypedStoreMut::attach(&mut deps.storage).store(CONFIG_KEY, &config)?;
+
+    Ok(HandleResponse::default())
+}
+
+fn create_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    entropy: String,
+) -> StdResult<HandleResponse> {
+    let prng_seed: Vec<u8> = read_prng_seed(&deps.storage)?;
+    let key: ViewingKey = ViewingKey::new(env, &prng_seed, entropy.as_bytes());
+    store_viewing_key(&mut deps.storage, &env.message.sender, &key.to_hashed());
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::CreateViewingKey { key })?),
+    })
+}
+
+fn set_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    key: String,
+) -> StdResult<HandleResponse> {
+    store_viewing_key(
+        &mut deps.storage,
+        &env.message.sender,
+        &ViewingKey(key).to_hashed(),
+    );
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::SetViewingKey {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    RevokedPermits::revoke_permit(
+        &mut deps.storage,
+        PREFIX_REVOKED_PERMITS,
+        &env.message.sender,
+        &permit_name,
+    );
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RevokePermit {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+// An address without a viewing key is checked against a dummy hash
+// so that it takes as long as a wrong key
+fn authenticate_viewing_key<S: Storage>(
+    storage: &S,
+    address: &HumanAddr,
+    key: String,
+) -> StdResult<()> {
+    let hashed_key: Vec<u8> = read_viewing_key(storage, address).unwrap_or_else(|| vec![0u8; 32]);
+    if !ViewingKey(key).check_viewing_key(&hashed_key) {
+        return Err(StdError::generic_err(
+            "Wrong viewing key for this address or viewing key not set.",
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => {
+            let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+            Ok(to_binary(&config)?)
+        }
+        QueryMsg::ContractInfo {} => query_contract_info(deps),
+        QueryMsg::Dcas {
+            address,
+            key,
+            page,
+            page_size,
+        } => {
+            authenticate_viewing_key(&deps.storage, &address, key)?;
+            query_dcas(deps, &address, page, page_size)
+        }
+        QueryMsg::MultiInputCollection { address, key } => {
+            authenticate_viewing_key(&deps.storage, &address, key)?;
+            query_multi_input_collection(deps, &address)
+        }
+        QueryMsg::Orders {
+            address,
+            key,
+            page,
+            page_size,
+        } => {
+            authenticate_viewing_key(&deps.storage, &address, key)?;
+            query_orders(deps, &address, page, page_size)
+        }
+        QueryMsg::PairOracle {
+            input_token,
+            output_token,
+        } => Ok(to_binary(&read_pair_oracle(
+            &deps.storage,
+            &input_token,
+            &output_token,
+        )?)?),
+        QueryMsg::RouteState { key } => {
+            let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+            authenticate_viewing_key(&deps.storage, &config.admin, key)?;
+            Ok(to_binary(&read_route_state(&deps.storage)?)?)
+        }
+        QueryMsg::Stats {} => query_stats(deps),
+        QueryMsg::SwapHistory {
+            address,
+            key,
+            page,
+            page_size,
+        } => {
+            authenticate_viewing_key(&deps.storage, &address, key)?;
+            query_swap_history(deps, &address, page, page_size)
+        }
+        QueryMsg::TokenStats { token } => Ok(to_binary(&read_token_stats(&deps.storage, &token)?)?),
+        QueryMsg::Tokens { page, page_size } => {
+            let mut tokens: Vec<TokenMetadata> = vec![];
+            for address in read_token_addresses(&deps.storage, page, page_size)? {
+                if let Some(metadata) = read_token_metadata(&deps.storage, &address)? {
+                    tokens.push(metadata);
+                }
+            }
+            Ok(to_binary(&tokens)?)
+        }
+        QueryMsg::WithPermit { permit, query } => permit_queries(deps, permit, query),
+    }
+}
+
+// The permission is checked before the signature so that the error is clear
+fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
+) -> StdResult<Binary> {
+    let allowed: bool = match query {
+        QueryWithPermit::SwapHistory { .. } => {
+            permit.check_permission(&TokenPermissions::History)
+                || permit.check_permission(&TokenPermissions::Owner)
+        }
+        _ => permit.check_permission(&TokenPermissions::Owner),
+    };
+    if !allowed {
+        return Err(StdError::generic_err(format!(
+            "No permission to query {:?}, got permissions {:?}.",
+            query, permit.params.permissions
+        )));
+    }
+    let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY)?;
+    let address: HumanAddr = HumanAddr(permit::validate(
+        deps,
+        PREFIX_REVOKED_PERMITS,
+        &permit,
+        config.contract_address,
+    )?);
+
+    match query {
+        QueryWithPermit::Dcas { page, page_size } => query_dcas(deps, &address, page, page_size),
+        QueryWithPermit::MultiInputCollection {} => query_multi_input_collection(deps, &address),
+        QueryWithPermit::Orders { page, page_size } => {
+            query_orders(deps, &address, page, page_size)
+        }
+        QueryWithPermit::SwapHistory { page, page_size } => {
+            query_swap_history(deps, &address, page, page_size)
+        }
+    }
+}
+
+fn query_contract_info<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<Binary> {
+    let status: ContractStatus = match read_route_state(&deps.storage)? {
+        Some(_) => ContractStatus::RouteStateLeftBehind,
+        None => ContractStatus::Operational,
+    };
+
+    to_binary(&ContractInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        hop_kinds: HOP_KINDS.iter().map(|kind| kind.to_string()).collect(),
+        protocols: vec![
+            Protocol::SecretSwap,
+            Protocol::ShadeSwap,
+            Protocol::SiennaSwap,
+            Protocol::StakingDerivative,
+            Protocol::WithdrawLiquidity,
+        ],
+        limits: ContractLimits {
+            min_hops: 2,
+            max_hops: None,
+            keeper_fee_basis_points: KEEPER_FEE_BASIS_POINTS as u64,
+            block_size: BLOCK_SIZE as u32,
+        },
+        status,
+    })
+}
+
+fn query_dcas<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let mut dcas: Vec<Dca> = vec![];
+    for id in read_user_dca_ids(&deps.storage, address)?
+        .into_iter()
+        .skip((page as usize).saturating_mul(page_size as usize))
+        .take(page_size as usize)
+    {
+        if let Some(dca) = read_dca(&deps.storage, id)? {
+            dcas.push(dca);
+        }
+    }
+    to_binary(&dcas)
+}
+
+fn query_multi_input_collection<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+) -> StdResult<Binary> {
+    let collection: Option<MultiInputCollection> =
+        read_multi_input_collection(&deps.storage, address)?;
+    to_binary(&collection)
+}
+
+fn query_orders<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let mut orders: Vec<LimitOrder> = vec![];
+    for id in read_user_order_ids(&deps.storage, address)?
+        .into_iter()
+        .skip((page as usize).saturating_mul(page_size as usize))
+        .take(page_size as usize)
+    {
+        if let Some(order) = read_order(&deps.storage, id)? {
+            orders.push(order);
+        }
+    }
+    to_binary(&orders)
+}
+
+fn query_stats<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>) -> StdResult<Binary> {
+    let mut hop_counts: Vec<HopCount> = vec![];
+    for hop_kind in HOP_KINDS.iter() {
+        hop_counts.push(HopCount {
+            hop_kind: hop_kind.to_string(),
+            hop_count: read_hop_count(&deps.storage, hop_kind)?,
+        });
+    }
+
+    to_binary(&Stats {
+        route_count: read_route_count(&deps.storage)?,
+        hop_counts,
+    })
+}
+
+fn query_swap_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Binary> {
+    let swaps: Vec<Swap> = read_swaps(&deps.storage, address, page, page_size)?;
+    to_binary(&swaps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{SecretContractForShadeProtocol, SiennaSwapHop, SiennaSwapTokenType};
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{from_slice, Empty, QuerierResult, QueryRequest, WasmQuery};
+    use secret_toolkit::permit::{PermitParams, PermitSignature, PubKey};
+    use std::collections::VecDeque;
+
+    // === MOCK QUERIER ===
+    // Answers smart queries to the mocked contracts
+    struct WasmMockQuerier {}
+
+    impl Querier for WasmMockQuerier {
+        fn raw_query(&self, request: &[u8]) -> QuerierResult {
+            let request: QueryRequest<Empty> = from_slice(request).unwrap();
+            match request {
+                QueryRequest::Wasm(WasmQuery::Smart { contract_addr, .. }) => {
+                    if contract_addr == mock_staking_derivative().address {
+                        Ok(to_binary(&snip20::BalanceResponse {
+                            balance: snip20::Balance {
+                                amount: mock_staking_derivative_balance(),
+                            },
+                        }))
+                    } else if contract_addr == mock_oracle().address {
+                        Ok(to_binary(&OracleReferenceData {
+                            rate: mock_oracle_rate(),
+                        }))
+                    } else if contract_addr == mock_zap_in().pair.address {
+                        Ok(to_binary(&SecretSwapPoolResponse {
+                            assets: [
+                                Asset {
+                                    info: mock_asset_info(mock_sscrt()),
+                                    amount: Uint128(1_000_000),
+                                },
+                                Asset {
+                                    info: mock_asset_info(mock_button()),
+                                    amount: Uint128(2_000_000),
+                                },
+                            ],
+                            total_share: Uint128(1_000_000),
+                        }))
+                    } else if contract_addr == mock_button().address {
+                        Ok(to_binary(&snip20::TokenInfoResponse {
+                            token_info: mock_token_info("Button", "BUTT", 6),
+                        }))
+                    } else if contract_addr == mock_token().address {
+                        Ok(to_binary(&snip20::TokenInfoResponse {
+                            token_info: mock_token_info("Token", "TKN", 18),
+                        }))
+                    } else if contract_addr == mock_sscrt().address {
+                        Ok(Err(StdError::generic_err("Unknown query.")))
+                    } else {
+                        panic!("Unexpected query to {}", contract_addr)
+                    }
+                }
+                _ => panic!("Unexpected query"),
+            }
+        }
+    }
+
+    // === HELPERS ===
+    fn mock_dependencies_with_wasm_querier() -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+        Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(20),
+            querier: WasmMockQuerier {},
+        }
+    }
+
+    fn init_helper() -> (
+        StdResult<InitResponse>,
+        Extern<MockStorage, MockApi, MockQuerier>,
+    ) {
+        let env = mock_env(mock_contract_initiator_address(), &[]);
+        let mut deps = mock_dependencies(20, &[]);
+        let msg = InitMsg {
+            entropy: "entropy".to_string(),
+            migrations: None,
+            pad_responses: Some(false),
+            wrapped_tokens: None,
+        };
+        (init(&mut deps, env, msg), deps)
+    }
+
+    fn mock_asset_info(token: SecretContract) -> AssetInfo {
+        AssetInfo::Token {
+            contract_addr: token.address,
+            token_code_hash: token.contract_hash,
+            viewing_key: "SecretSwap".to_string(),
+        }
+    }
+
+    fn mock_button() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-button-address"),
+            contract_hash: "mock-button-contract-hash".to_string(),
+        }
+    }
+
+    fn mock_contract() -> SecretContract {
+        let env = mock_env(mock_user_address(), &[]);
+        SecretContract {
+            address: env.contract.address,
+            contract_hash: env.contract_code_hash,
+        }
+    }
+
+    fn mock_contract_initiator_address() -> HumanAddr {
+        HumanAddr::from("btn.group")
+    }
+
+    fn mock_denom() -> String {
+        "uatom".to_string()
+    }
+
+    fn mock_merchant_address() -> HumanAddr {
+        HumanAddr::from("merchant")
+    }
+
+    fn mock_oracle() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-oracle-address"),
+            contract_hash: "mock-oracle-contract-hash".to_string(),
+        }
+    }
+
+    // 2 USD per SCRT
+    fn mock_oracle_rate() -> Uint128 {
+        Uint128(2_000_000_000_000_000_000)
+    }
+
+    fn mock_pair_contract() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("pair-contract-address"),
+            contract_hash: "pair-contract-contract-hash".to_string(),
+        }
+    }
+
+    fn mock_pair_contract_two() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("pair-contract-two-address"),
+            contract_hash: "pair-contract-two-hash".to_string(),
+        }
+    }
+
+    fn mock_shade_protocol_router() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-shade-protocol-router-address"),
+            contract_hash: "mock-shade-protocol-router-contract-hash".to_string(),
+        }
+    }
+
+    fn mock_sienna_swap_router() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-sienna-swap-router-address"),
+            contract_hash: "mock-sienna-swap-router-contract-hash".to_string(),
+        }
+    }
+
+    fn mock_sscrt() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-sscrt-address"),
+            contract_hash: "mock-sscrt-contract-hash".to_string(),
+        }
+    }
+
+    fn mock_staking_derivative() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-staking-derivative-address"),
+            contract_hash: "mock-staking-derivative-contract-hash".to_string(),
+        }
+    }
+
+    // This contract's balance of the derivative
+    fn mock_staking_derivative_balance() -> Uint128 {
+        Uint128(1_000)
+    }
+
+    fn mock_token_info(name: &str, symbol: &str, decimals: u8) -> snip20::TokenInfo {
+        snip20::TokenInfo {
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            decimals,
+            total_supply: None,
+        }
+    }
+
+    fn mock_token() -> SecretContract {
+        SecretContract {
+            address: HumanAddr::from("mock-token-address"),
+            contract_hash: "mock-token-contract-hash".to_string(),
+        }
+    }
+
+    fn mock_token_native() -> Token {
+        Token::Native(mock_sscrt())
+    }
+
+    fn mock_token_snip20() -> Token {
+        Token::Snip20(mock_sscrt())
+    }
+
+    fn mock_user_address() -> HumanAddr {
+        HumanAddr::from("gary")
+    }
+
+    fn mock_wrapped_token() -> WrappedToken {
+        WrappedToken {
+            token: mock_sscrt().address,
+            denom: mock_denom(),
+        }
+    }
+
+    fn mock_zap_in() -> ZapIn {
+        ZapIn {
+            pair: SecretContract {
+                address: HumanAddr::from("mock-zap-in-pair-address"),
+                contract_hash: "mock-zap-in-pair-contract-hash".to_string(),
+            },
+            liquidity_token: SecretContract {
+                address: HumanAddr::from("mock-zap-in-liquidity-token-address"),
+                contract_hash: "mock-zap-in-liquidity-token-contract-hash".to_string(),
+            },
+        }
+    }
+
+    // === QUERY TESTS ===
+    #[test]
+    fn test_permits() {
+        let (_init_result, mut deps) = init_helper();
+        let permit_query = |allowed_tokens: Vec<HumanAddr>, permissions: Vec<TokenPermissions>| {
+            QueryMsg::WithPermit {
+                permit: Permit {
+                    params: PermitParams {
+                        allowed_tokens,
+                        permit_name: "aggregator".to_string(),
+                        chain_id: "secret-4".to_string(),
+                        permissions,
+                    },
+                    signature: PermitSignature {
+                        pub_key: PubKey {
+                            r#type: "tendermint/PubKeySecp256k1".to_string(),
+                            value: Binary::from(&[2u8; 33][..]),
+                        },
+                        signature: Binary::from(&[0u8; 64][..]),
+                    },
+                },
+                query: QueryWithPermit::Orders {
+                    page: 0,
+                    page_size: 10,
+                },
+            }
+        };
+
+        // when a permit is queried without the permission for the query
+        let query_result = query(
+            &deps,
+            permit_query(
+                vec![MOCK_CONTRACT_ADDR.into()],
+                vec![TokenPermissions::History],
+            ),
+        );
+        // * it raises an error
+        assert_eq!(
+            query_result.unwrap_err(),
+            StdError::generic_err(format!(
+                "No permission to query {:?}, got permissions {:?}.",
+                QueryWithPermit::Orders {
+                    page: 0,
+                    page_size: 10,
+                },
+                vec![TokenPermissions::History]
+            ))
+        );
+
+        // when a permit doesn't allow this contract
+        let query_result = query(
+            &deps,
+            permit_query(vec![mock_button().address], vec![TokenPermissions::Owner]),
+        );
+        // * it raises an error
+        assert!(query_result.is_err());
+
+        // when a permit is revoked
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::RevokePermit {
+                permit_name: "aggregator".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        // * it returns a success status
+        assert_eq!(
+            from_binary::<HandleAnswer>(&handle_result.data.unwrap()).unwrap(),
+            HandleAnswer::RevokePermit {
+                status: ResponseStatus::Success
+            }
+        );
+        // * it stores the revocation for the sender
+        assert!(RevokedPermits::is_permit_revoked(
+            &deps.storage,
+            PREFIX_REVOKED_PERMITS,
+            &mock_user_address(),
+            "aggregator"
+        ));
+
+        // when a permit is signed by the querier
+        let signer: HumanAddr = HumanAddr::from("secret134sx2txr0r558x03ttdzujn5xxuzxt5gmnf8lw");
+        let order: LimitOrder = LimitOrder {
+            id: 0,
+            owner: signer.clone(),
+            route_template: Route {
+                hops: VecDeque::new(),
+                estimated_amount: Uint128(2_000),
+                minimum_acceptable_amount: Uint128(1_500),
+                to: signer.clone(),
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            },
+            amount: Uint128(1_000),
+            denom: None,
+            min_output: Uint128(1_500),
+            expiration: None,
+            trigger: None,
+        };
+        store_order(&mut deps.storage, &order).unwrap();
+        let swap: Swap = Swap {
+            inputs: vec![TokenAmount {
+                token: mock_sscrt().address,
+                amount: Uint128(1_000),
+            }],
+            output: TokenAmount {
+                token: mock_button().address,
+                amount: Uint128(2_000),
+            },
+            fee_amount: Uint128(0),
+            block_height: 12_345,
+            hop_count: 1,
+        };
+        append_swap(&mut deps.storage, &signer, &swap).unwrap();
+        let signed_permit_query = |query: QueryWithPermit| {
+            QueryMsg::WithPermit {
+            permit: Permit {
+                params: PermitParams {
+                    allowed_tokens: vec![MOCK_CONTRACT_ADDR.into()],
+                    permit_name: "aggregator".to_string(),
+                    chain_id: "secret-4".to_string(),
+                    permissions: vec![TokenPermissions::Owner],
+                },
+                signature: PermitSignature {
+                    pub_key: PubKey {
+                        r#type: "tendermint/PubKeySecp256k1".to_string(),
+                        value: Binary::from_base64("An4gkuSWZt/SVB8UZzoKmItmW8ZqqeCFUo9pPzpnJ+Sq")
+                            .unwrap(),
+                    },
+                    signature: Binary::from_base64(
+                        "Kj3274c/nZ6xQRTkkY3c69mNnmqiztiX1rEYqZH0UahOiCpOo3E9X4Lr60EJuqFhPHURD6byyISqc0xZgyFyDw==",
+                    )
+                    .unwrap(),
+                },
+            },
+            query,
+        }
+        };
+        // * it returns the signer's orders
+        let query_result = query(
+            &deps,
+            signed_permit_query(QueryWithPermit::Orders {
+                page: 0,
+                page_size: 10,
+            }),
+        )
+        .unwrap();
+        let orders: Vec<LimitOrder> = from_binary(&query_result).unwrap();
+        assert_eq!(orders, vec![order]);
+        // * it returns the signer's swap history
+        let query_result = query(
+            &deps,
+            signed_permit_query(QueryWithPermit::SwapHistory {
+                page: 0,
+                page_size: 10,
+            }),
+        )
+        .unwrap();
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(swaps, vec![swap]);
+    }
+
+    #[test]
+    fn test_query_config() {
+        let (_init_result, deps) = init_helper();
+        let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
+        let query_result = query(&deps, QueryMsg::Config {}).unwrap();
+        let query_answer_config: Config = from_binary(&query_result).unwrap();
+        assert_eq!(query_answer_config, config);
+    }
+
+    #[test]
+    fn test_query_contract_info() {
+        let (_init_result, mut deps) = init_helper();
+
+        // when no route state is left behind
+        let query_result = query(&deps, QueryMsg::ContractInfo {}).unwrap();
+        let contract_info: ContractInfo = from_binary(&query_result).unwrap();
+        // * it returns the crate version
+        assert_eq!(contract_info.version, "5.0.0");
+        // * it returns the hop kinds that are logged
+        assert!(contract_info
+            .hop_kinds
+            .contains(&hop_kind(&swap_hop(mock_token_snip20(), None)).to_string()));
+        assert!(contract_info
+            .hop_kinds
+            .contains(&hop_kind(&swap_hop(mock_token_native(), None)).to_string()));
+        // * it returns the limits
+        assert_eq!(
+            contract_info.limits,
+            ContractLimits {
+                min_hops: 2,
+                max_hops: None,
+                keeper_fee_basis_points: 10,
+                block_size: 256,
+            }
+        );
+        // * it is operational
+        assert_eq!(contract_info.status, ContractStatus::Operational);
+
+        // when a route state is left behind
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: swap_hop(mock_token_snip20(), Some(mock_pair_contract())),
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: VecDeque::new(),
+                    estimated_amount: Uint128(2_000),
+                    minimum_acceptable_amount: Uint128(1_500),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        let query_result = query(&deps, QueryMsg::ContractInfo {}).unwrap();
+        let contract_info: ContractInfo = from_binary(&query_result).unwrap();
+        // * it reports the route state
+        assert_eq!(contract_info.status, ContractStatus::RouteStateLeftBehind);
+    }
+
+    #[test]
+    fn test_query_route_state() {
+        let (_init_result, mut deps) = init_helper();
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "admin-key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "user-key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        let route_state_query = |key: &str| QueryMsg::RouteState {
+            key: key.to_string(),
+        };
+
+        // when queried with a key that isn't the admin's
+        let query_result = query(&deps, route_state_query("user-key"));
+        // * it raises an error
+        assert_eq!(
+            query_result.unwrap_err(),
+            StdError::generic_err("Wrong viewing key for this address or viewing key not set.")
+        );
+
+        // when queried with the admin's key
+        // = when there is no route state
+        let query_result = query(&deps, route_state_query("admin-key")).unwrap();
+        // = * it returns None
+        let route_state: Option<RouteState> = from_binary(&query_result).unwrap();
+        assert!(route_state.is_none());
+        // = when a route was left behind
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&Route {
+                        hops: hops.clone(),
+                        estimated_amount: Uint128(2_000),
+                        minimum_acceptable_amount: Uint128(1_500),
+                        to: mock_user_address(),
+                        allow_third_party_recipient: None,
+                        zap_in: None,
+                        min_profit: None,
+                        private: None,
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(1_000),
+            },
+        )
+        .unwrap();
+        let query_result = query(&deps, route_state_query("admin-key")).unwrap();
+        // = * it returns the route state
+        let route_state: RouteState = from_binary::<Option<RouteState>>(&query_result)
+            .unwrap()
+            .unwrap();
+        assert_eq!(route_state.current_hop, hops[0]);
+        assert_eq!(
+            route_state.remaining_route.hops,
+            hops.into_iter().skip(1).collect::<VecDeque<Hop>>()
+        );
+        assert_eq!(route_state.user, mock_user_address());
+    }
+
+    #[test]
+    fn test_stats() {
+        let (_init_result, mut deps) = init_helper();
+
+        let hop_counts = |secret_swap_hop_count: u64| -> Vec<HopCount> {
+            HOP_KINDS
+                .iter()
+                .map(|hop_kind| HopCount {
+                    hop_kind: hop_kind.to_string(),
+                    hop_count: if *hop_kind == "secret_swap" {
+                        secret_swap_hop_count
+                    } else {
+                        0
+                    },
+                })
+                .collect()
+        };
+
+        // when no route has completed
+        let query_result = query(&deps, QueryMsg::Stats {}).unwrap();
+        // * it returns zero counts for every hop kind
+        let stats: Stats = from_binary(&query_result).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                route_count: 0,
+                hop_counts: hop_counts(0),
+            }
+        );
+
+        // when a route pays out
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&Route {
+                        hops,
+                        estimated_amount: Uint128(2_000),
+                        minimum_acceptable_amount: Uint128(1_500),
+                        to: mock_user_address(),
+                        allow_third_party_recipient: None,
+                        zap_in: None,
+                        min_profit: None,
+                        private: None,
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(1_000),
+            },
+        )
+        .unwrap();
+        handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract().address,
+                msg: None,
+                amount: Uint128(2_100),
+            },
+        )
+        .unwrap();
+        // * it counts the route and its hops by kind
+        let query_result = query(&deps, QueryMsg::Stats {}).unwrap();
+        let stats: Stats = from_binary(&query_result).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                route_count: 1,
+                hop_counts: hop_counts(1),
+            }
+        );
+        // * it adds the volumes and the fee to each token's stats
+        let token_stats = |token: HumanAddr| -> TokenStats {
+            from_binary(&query(&deps, QueryMsg::TokenStats { token }).unwrap()).unwrap()
+        };
+        assert_eq!(
+            token_stats(mock_sscrt().address),
+            TokenStats {
+                input_volume: Uint128(1_000),
+                output_volume: Uint128::zero(),
+                fees: Uint128::zero(),
+            }
+        );
+        assert_eq!(
+            token_stats(mock_button().address),
+            TokenStats {
+                input_volume: Uint128::zero(),
+                output_volume: Uint128(2_000),
+                fees: Uint128(100),
+            }
+        );
+    }
+
+    #[test]
+    fn test_swap_history() {
+        let (_init_result, mut deps) = init_helper();
+        let swap_history_query = |key: &str, page: u32| QueryMsg::SwapHistory {
+            address: mock_user_address(),
+            key: key.to_string(),
+            page,
+            page_size: 10,
+        };
+
+        // when a viewing key is created
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::CreateViewingKey {
+                entropy: "entropy".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        // * it returns the viewing key
+        let key: String = match from_binary(&handle_result.data.unwrap()).unwrap() {
+            HandleAnswer::CreateViewingKey { key } => key.0,
+            _ => panic!("unexpected answer"),
+        };
+        assert!(key.starts_with("api_key_"));
+        // = when the swap history is queried with the wrong key
+        let query_result = query(&deps, swap_history_query("wrong key", 0));
+        // = * it raises an error
+        assert_eq!(
+            query_result.unwrap_err(),
+            StdError::generic_err("Wrong viewing key for this address or viewing key not set.")
+        );
+        // = when the swap history is queried with the viewing key
+        let query_result = query(&deps, swap_history_query(&key, 0)).unwrap();
+        // = * it returns an empty history
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(swaps, vec![]);
+
+        // when a route pays out
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&Route {
+                        hops,
+                        estimated_amount: Uint128(2_000),
+                        minimum_acceptable_amount: Uint128(1_500),
+                        to: mock_user_address(),
+                        allow_third_party_recipient: None,
+                        zap_in: None,
+                        min_profit: None,
+                        private: None,
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(1_000),
+            },
+        )
+        .unwrap();
+        handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract().address,
+                msg: None,
+                amount: Uint128(2_100),
+            },
+        )
+        .unwrap();
+        // * it records the swap in the user's history
+        let query_result = query(&deps, swap_history_query(&key, 0)).unwrap();
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(
+            swaps,
+            vec![Swap {
+                inputs: vec![TokenAmount {
+                    token: mock_sscrt().address,
+                    amount: Uint128(1_000),
+                }],
+                output: TokenAmount {
+                    token: mock_button().address,
+                    amount: Uint128(2_000),
+                },
+                fee_amount: Uint128(100),
+                block_height: 12_345,
+                hop_count: 1,
+            }]
+        );
+        // = when the next page is queried
+        let query_result = query(&deps, swap_history_query(&key, 1)).unwrap();
+        // = * it returns no swaps
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(swaps, vec![]);
+        // = when a page beyond the u32 range of items is queried
+        let query_result = query(&deps, swap_history_query(&key, u32::MAX)).unwrap();
+        // = * it returns no swaps
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(swaps, vec![]);
+
+        // when the viewing key is set
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "new key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        // * it returns a success status
+        assert_eq!(
+            from_binary::<HandleAnswer>(&handle_result.data.unwrap()).unwrap(),
+            HandleAnswer::SetViewingKey {
+                status: ResponseStatus::Success
+            }
+        );
+        // * it replaces the previous key
+        assert!(query(&deps, swap_history_query(&key, 0)).is_err());
+        let query_result = query(&deps, swap_history_query("new key", 0)).unwrap();
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(swaps.len(), 1);
+    }
+
+    // === HANDLE TESTS ===
+    #[test]
+    fn test_dca() {
+        let (_init_result, mut deps) = init_helper();
+        let budget: Uint128 = Uint128(2_500_000);
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let route_template: Route = Route {
+            hops: hops.clone(),
+            estimated_amount: Uint128(2_000_000),
+            minimum_acceptable_amount: Uint128(1_800_000),
+            to: mock_user_address(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: None,
+            private: None,
+        };
+        let schedule: DcaSchedule = DcaSchedule {
+            amount_per_period: Uint128(1_000_000),
+            period: DcaPeriod::Blocks(100),
+            min_output_per_period: Uint128(1_900_000),
+        };
+
+        // when creating a schedule with a zero period
+        for period in [DcaPeriod::Blocks(0), DcaPeriod::Seconds(0)].iter() {
+            let handle_result = handle(
+                &mut deps,
+                mock_env(mock_sscrt().address, &[]),
+                HandleMsg::Receive {
+                    from: mock_user_address(),
+                    msg: Some(
+                        to_binary(&ReceiveMsg::CreateDca {
+                            route_template: route_template.clone(),
+                            schedule: DcaSchedule {
+                                period: period.clone(),
+                                ..schedule.clone()
+                            },
+                        })
+                        .unwrap(),
+                    ),
+                    amount: budget,
+                },
+            );
+            // * it raises an error
+            assert_eq!(
+                handle_result.unwrap_err(),
+                StdError::generic_err("period must be greater than zero.")
+            );
+        }
+
+        // when creating a schedule with the input token
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&ReceiveMsg::CreateDca {
+                    route_template: route_template.clone(),
+                    schedule: schedule.clone(),
+                })
+                .unwrap(),
+            ),
+            amount: budget,
+        };
+        let env = mock_env(mock_sscrt().address, &[]);
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // * it escrows the budget and logs the schedule id
+        assert!(handle_result_unwrapped.messages.is_empty());
+        assert_eq!(handle_result_unwrapped.log, vec![log("dca_id", "0")]);
+        // * it can be queried by the owner's address and viewing key
+        handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        let query_result = query(
+            &deps,
+            QueryMsg::Dcas {
+                address: mock_user_address(),
+                key: "key".to_string(),
+                page: 0,
+                page_size: 10,
+            },
+        )
+        .unwrap();
+        let dcas: Vec<Dca> = from_binary(&query_result).unwrap();
+        assert_eq!(
+            dcas,
+            vec![Dca {
+                id: 0,
+                owner: mock_user_address(),
+                route_template: route_template.clone(),
+                budget,
+                denom: None,
+                schedule: schedule.clone(),
+                next_execution: env.block.height,
+            }]
+        );
+
+        // when a keeper executes the schedule
+        let mut env = mock_env(mock_merchant_address(), &[]);
+        let handle_msg = HandleMsg::ExecuteDca {
+            id: 0,
+            route: route_template.clone(),
+        };
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg.clone()).unwrap();
+        // * it sends the period's amount along the route and then finalizes the route
+        let mut messages = hop_messages(hops[0].clone(), Uint128(1_000_000), &env).unwrap();
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: mock_contract().address,
+            callback_code_hash: mock_contract().contract_hash,
+            msg: to_binary(&HandleMsg::FinalizeRoute {}).unwrap(),
+            send: vec![],
+        }));
+        assert_eq!(handle_result_unwrapped.messages, messages);
+        // * it stores the route state with the period's minimum and the keeper
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            Uint128(1_900_000)
+        );
+        assert_eq!(route_state.keeper, Some(mock_merchant_address()));
+        delete_route_state(&mut deps.storage);
+        // * it reduces the budget and schedules the next execution
+        let dca: Dca = read_dca(&deps.storage, 0).unwrap().unwrap();
+        assert_eq!(dca.budget, Uint128(1_500_000));
+        assert_eq!(dca.next_execution, env.block.height + 100);
+
+        // = when the period hasn't passed yet
+        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("DCA period hasn't passed yet.")
+        );
+
+        // = when the budget is less than the amount per period
+        env.block.height += 100;
+        handle(&mut deps, env.clone(), handle_msg.clone()).unwrap();
+        delete_route_state(&mut deps.storage);
+        env.block.height += 100;
+        handle(&mut deps, env.clone(), handle_msg.clone()).unwrap();
+        // = * it swaps the rest of the budget with a proportional minimum
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            Uint128(950_000)
+        );
+        delete_route_state(&mut deps.storage);
+        // = * it removes the schedule
+        assert_eq!(read_dca(&deps.storage, 0).unwrap(), None);
+
+        // when cancelling a schedule
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&ReceiveMsg::CreateDca {
+                    route_template: route_template.clone(),
+                    schedule: schedule.clone(),
+                })
+                .unwrap(),
+            ),
+            amount: budget,
+        };
+        handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        // = when called by someone other than the owner
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_merchant_address(), &[]),
+            HandleMsg::CancelDca { id: 1 },
+        );
+        // = * it raises an Unauthorized error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+        // = when called by the owner
+        let handle_result_unwrapped = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::CancelDca { id: 1 },
+        )
+        .unwrap();
+        // = * it refunds the rest of the budget and removes the schedule
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![snip20::transfer_msg(
+                mock_user_address(),
+                budget,
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap()]
+        );
+        assert_eq!(read_dca(&deps.storage, 1).unwrap(), None);
+
+        // when a schedule swaps 18 decimal amounts
+        let amount_per_period: Uint128 = Uint128(1_000 * 10u128.pow(18));
+        handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&ReceiveMsg::CreateDca {
+                        route_template: route_template.clone(),
+                        schedule: DcaSchedule {
+                            amount_per_period,
+                            period: DcaPeriod::Blocks(100),
+                            min_output_per_period: Uint128(2_000 * 10u128.pow(18)),
+                        },
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(1_500 * 10u128.pow(18)),
+            },
+        )
+        .unwrap();
+        let handle_msg = HandleMsg::ExecuteDca {
+            id: 2,
+            route: route_template,
+        };
+        handle(&mut deps, env.clone(), handle_msg.clone()).unwrap();
+        // * it uses the full minimum for a full period
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            Uint128(2_000 * 10u128.pow(18))
+        );
+        delete_route_state(&mut deps.storage);
+        // = when the rest of the budget is swapped
+        env.block.height += 100;
+        handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // = * it scales the minimum down without overflowing
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            Uint128(1_000 * 10u128.pow(18))
+        );
+        delete_route_state(&mut deps.storage);
+
+        // when schedules are escrowed in two native denoms
+        let native_route_template = |denom: String| -> Route {
+            let mut hops: VecDeque<Hop> = VecDeque::new();
+            hops.push_back(Hop {
+                redeem_denom: Some(denom),
+                ..swap_hop(mock_token_native(), None)
+            });
+            hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+            hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+            Route {
+                hops,
+                estimated_amount: Uint128(2_000_000),
+                minimum_acceptable_amount: Uint128(1_800_000),
+                to: mock_user_address(),
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            }
+        };
+        for denom in &["uscrt".to_string(), mock_denom()] {
+            handle(
+                &mut deps,
+                mock_env(
+                    mock_user_address(),
+                    &[Coin {
+                        denom: denom.clone(),
+                        amount: budget,
+                    }],
+                ),
+                HandleMsg::Receive {
+                    from: mock_user_address(),
+                    msg: Some(
+                        to_binary(&ReceiveMsg::CreateDca {
+                            route_template: native_route_template(denom.clone()),
+                            schedule: schedule.clone(),
+                        })
+                        .unwrap(),
+                    ),
+                    amount: budget,
+                },
+            )
+            .unwrap();
+        }
+        // = when the keeper's first hop uses the other schedule's denom
+        let mut invalid_route: Route = native_route_template("uscrt".to_string());
+        invalid_route.hops[0] = Hop {
+            redeem_denom: Some(mock_denom()),
+            protocol: Some(Protocol::StakingDerivative),
+            ..swap_hop(mock_token_native(), Some(mock_staking_derivative()))
+        };
+        let handle_result = handle(
+            &mut deps,
+            env,
+            HandleMsg::ExecuteDca {
+                id: 3,
+                route: invalid_route,
+            },
+        );
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Route must start and end with the order's tokens.")
+        );
+        // = * both denoms stay escrowed
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, "uscrt").unwrap(),
+            budget
+        );
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, &mock_denom()).unwrap(),
+            budget
+        );
+    }
+
+    #[test]
+    fn test_execute_arbitrage() {
+        let (_init_result, mut deps) = init_helper();
+        let amount: Uint128 = Uint128(1_000_000);
+        let min_profit: Uint128 = Uint128(1_000);
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(
+            Token::Snip20(mock_button()),
+            Some(mock_pair_contract_two()),
+        ));
+        let mut route: Route = Route {
+            hops: hops.clone(),
+            estimated_amount: Uint128(1_010_000),
+            minimum_acceptable_amount: Uint128(1),
+            to: mock_user_address(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: Some(min_profit),
+            private: None,
+        };
+
+        // when a user sends in an arbitrage route that doesn't end with the input token
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(to_binary(&route).unwrap()),
+            amount,
+        };
+        let handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Arbitrage route must start and end with the same token.")
+        );
+
+        // when a user sends in a cyclic arbitrage route
+        route.hops.push_back(swap_hop(mock_token_snip20(), None));
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(to_binary(&route).unwrap()),
+            amount,
+        };
+        handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        // * it requires the output to be at least the input plus min_profit
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            amount + min_profit
+        );
+        assert_eq!(route_state.remaining_route.to, mock_user_address());
+        delete_route_state(&mut deps.storage);
+
+        // when called by someone who isn't a keeper
+        let handle_msg = HandleMsg::ExecuteArbitrage {
+            amount,
+            route: route.clone(),
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            handle_msg.clone(),
+        );
+        // * it raises an Unauthorized error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when called by a keeper
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetKeepers {
+                keepers: vec![mock_user_address()],
+            },
+        )
+        .unwrap();
+        // = when the route doesn't set min_profit
+        let mut invalid_route: Route = route.clone();
+        invalid_route.min_profit = None;
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::ExecuteArbitrage {
+                amount,
+                route: invalid_route,
+            },
+        );
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Arbitrage route must set min_profit.")
+        );
+        // = when the route zaps in or pays a third party
+        let mut zap_in_route: Route = route.clone();
+        zap_in_route.zap_in = Some(mock_zap_in());
+        let mut third_party_route: Route = route.clone();
+        third_party_route.allow_third_party_recipient = Some(true);
+        for invalid_route in [zap_in_route, third_party_route] {
+            let handle_result = handle(
+                &mut deps,
+                mock_env(mock_user_address(), &[]),
+                HandleMsg::ExecuteArbitrage {
+                    amount,
+                    route: invalid_route,
+                },
+            );
+            // = * it raises an error
+            assert_eq!(
+                handle_result.unwrap_err(),
+                StdError::generic_err("Arbitrage route can't zap in or pay a third party.")
+            );
+        }
+        // = when the route sets min_profit
+        let env = mock_env(mock_user_address(), &[]);
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // = * it stores the route state with this contract as the recipient
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.remaining_route.to, mock_contract().address);
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            amount + min_profit
+        );
+        // = * it sends the contract's own tokens along the first hop and then finalizes the route
+        let mut messages = hop_messages(hops[0].clone(), amount, &env).unwrap();
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: mock_contract().address,
+            callback_code_hash: mock_contract().contract_hash,
+            msg: to_binary(&HandleMsg::FinalizeRoute {}).unwrap(),
+            send: vec![],
+        }));
+        assert_eq!(handle_result_unwrapped.messages, messages);
+
+        // = when the output is less than the input plus min_profit
+        handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract().address,
+                msg: None,
+                amount: Uint128(2_000_000),
+            },
+        )
+        .unwrap();
+        let handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract_two().address,
+            msg: None,
+            amount: amount + Uint128(999),
+        };
+        let handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Operation fell short of minimum_acceptable_amount")
+        );
+        // = when the output is at least the input plus min_profit
+        let handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract_two().address,
+            msg: None,
+            amount: Uint128(1_020_000),
+        };
+        let handle_result_unwrapped =
+            handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        // = * it keeps the output in the contract as protocol revenue
+        assert!(handle_result_unwrapped.messages.is_empty());
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_sscrt().address),
+                log("fee_amount", "0"),
+                log("payout_recipient", MOCK_CONTRACT_ADDR),
+                log("payout_token", mock_sscrt().address),
+                log("return_amount", Uint128(1_020_000).to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_finalize_route() {
+        let (_init_result, mut deps) = init_helper();
+        let env = mock_env(mock_user_address(), &[]);
+
+        // when route state does not exist
+        // * it raises an error
+        let handle_msg = HandleMsg::FinalizeRoute {};
+        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("no route to finalize")
+        );
+
+        // when route state exists
+        // = when there are hops
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(Hop {
+            from_token: mock_token_native(),
+            redeem_denom: None,
+            smart_contract: Some(mock_pair_contract()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        let route_state: RouteState = RouteState {
+            current_hop: Hop {
+                from_token: mock_token_native(),
+                redeem_denom: None,
+                smart_contract: Some(mock_pair_contract()),
+                migrate_to_token: None,
+                shade_protocol_router_path: None,
+                protocol: None,
+                sienna_swap_router_path: None,
+                token_out: None,
+                minimum_output: None,
+            },
+            hop_index: 1,
+            remaining_route: Route {
+                hops: hops,
+                estimated_amount: Uint128(1_000_000),
+                minimum_acceptable_amount: Uint128(1_000_000),
+                to: mock_user_address(),
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            },
+            zap_in_retained_amount: None,
+            zap_out_retained_amount: None,
+            balance_before_mint: None,
+            keeper: None,
+            oracle_minimum_amount: None,
+            remaining_inputs: VecDeque::new(),
+            collected_amount: None,
+            user: mock_user_address(),
+            swap: Swap::default(),
+        };
+        store_route_state(&mut deps.storage, &route_state).unwrap();
+        // == when it isn't called by the contract
+        // == * it raises an error
+        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+        // == when it's called by the contract
+        // == * it raises an error
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_contract().address, &[]),
+            handle_msg.clone(),
+        );
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err(format!(
+                "cannot finalize: route still contains hops: {:?}",
+                route_state.remaining_route
+            ))
+        );
+
+        // = when there are no hops
+        let hops: VecDeque<Hop> = VecDeque::new();
+        let route_state: RouteState = RouteState {
+            current_hop: Hop {
+                from_token: mock_token_native(),
+                redeem_denom: None,
+                smart_contract: Some(mock_pair_contract()),
+                migrate_to_token: None,
+                shade_protocol_router_path: None,
+                protocol: None,
+                sienna_swap_router_path: None,
+                token_out: None,
+                minimum_output: None,
+            },
+            hop_index: 1,
+            remaining_route: Route {
+                hops: hops,
+                estimated_amount: Uint128(1_000_000),
+                minimum_acceptable_amount: Uint128(1_000_000),
+                to: mock_user_address(),
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            },
+            zap_in_retained_amount: None,
+            zap_out_retained_amount: None,
+            balance_before_mint: None,
+            keeper: None,
+            oracle_minimum_amount: None,
+            remaining_inputs: VecDeque::new(),
+            collected_amount: None,
+            user: mock_user_address(),
+            swap: Swap::default(),
+        };
+        store_route_state(&mut deps.storage, &route_state).unwrap();
+        // == when it isn't called by the contract
+        // == * it raises an error
+        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+        // == when it's called by the contract
+        // == * it returns an Ok response
+        handle(
+            &mut deps,
+            mock_env(mock_contract().address, &[]),
+            handle_msg.clone(),
+        )
+        .unwrap();
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_contract().address, &[]),
+            handle_msg,
+        );
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("no route to finalize")
+        );
+    }
+
+    #[test]
+    fn test_handle_first_hop() {
+        let (_init_result, mut deps) = init_helper();
+        let minimum_acceptable_amount: Uint128 = Uint128(1_000_000);
+        let estimated_amount: Uint128 = Uint128(10_000_000);
+        let transaction_amount: Uint128 = minimum_acceptable_amount;
+        let env = mock_env(
+            mock_user_address(),
+            &[Coin {
+                denom: mock_denom(),
+                amount: transaction_amount,
+            }],
+        );
+
+        // when there is less than 2 hops
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(Hop {
+            from_token: mock_token_native(),
+            redeem_denom: Some(mock_denom()),
+            smart_contract: Some(mock_pair_contract()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Route must have at least 2 hops.")
+        );
+
+        // when there is 2 or more hops
+        // = when the amount specified does match the amount sent in
+        hops.push_back(Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_pair_contract_two()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount + transaction_amount,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        // == * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Received crypto type or amount is wrong.")
+        );
+        // = when the denom sent isn't the first hop's redeem_denom
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env(
+                mock_user_address(),
+                &[Coin {
+                    denom: "uscrt".to_string(),
+                    amount: transaction_amount,
+                }],
+            ),
+            handle_msg.clone(),
+        );
+        // == * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Received crypto type or amount is wrong.")
+        );
+        // = when the first hop's wrapped token isn't whitelisted
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        // == * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Wrapped token isn't whitelisted.")
+        );
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetWrappedTokens {
+                wrapped_tokens: vec![mock_wrapped_token()],
+            },
+        )
+        .unwrap();
+        // = when the amount specified matches the amount sent in
+        // == when the to does not match the user
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_pair_contract().address,
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        // == * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+        // == when the to does not match the user and a third party recipient is allowed
+        // === when the to is this contract
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_contract().address,
+                    allow_third_party_recipient: Some(true),
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        // === * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Recipient can't be this contract.")
+        );
+        // === when the to is a smart contract in the route
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_pair_contract_two().address,
+                    allow_third_party_recipient: Some(true),
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        let handle_result = handle(&mut deps, env.clone(), handle_msg);
+        // === * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Recipient can't be a smart contract in the route.")
+        );
+        // === when the to is a third party address
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_merchant_address(),
+                    allow_third_party_recipient: Some(true),
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // === * it stores the route state with the third party as the recipient
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.remaining_route.to, mock_merchant_address());
+        delete_route_state(&mut deps.storage);
+
+        // == when the to matches the sender
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops: hops.clone(),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                })
+                .unwrap(),
+            ),
+            amount: transaction_amount,
+        };
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // == * it stores the route state
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, hops.pop_front().unwrap());
+        assert_eq!(
+            route_state.remaining_route,
+            Route {
+                hops,
+                estimated_amount,
+                minimum_acceptable_amount,
+                to: mock_user_address(),
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            }
+        );
+        // == * it creates messages based on the first hop and then finalizes the route
+        let mut hop_messages =
+            hop_messages(route_state.current_hop, transaction_amount, &env).unwrap();
+        hop_messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: mock_contract().address,
+            callback_code_hash: mock_contract().contract_hash.clone(),
+            msg: to_binary(&HandleMsg::FinalizeRoute {}).unwrap(),
+            send: vec![],
+        }));
+        assert_eq!(handle_result_unwrapped.messages, hop_messages);
+        // == * it logs the route's input and the first hop
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("route_input_token", mock_sscrt().address),
+                log("route_input_amount", transaction_amount),
+                log("hop_index", "0"),
+                log("hop_kind", "deposit"),
+                log("hop_token_in", mock_sscrt().address),
+                log("hop_token_out", mock_sscrt().address),
+                log("hop_amount_in", transaction_amount),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_handle_hop() {
+        let (_init_result, mut deps) = init_helper();
+        let minimum_acceptable_amount: Uint128 = Uint128(1_000_000);
+        let estimated_amount: Uint128 = Uint128(10_000_000);
+        let transaction_amount: Uint128 = minimum_acceptable_amount;
+
+        // where there are no hops
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    from_token: mock_token_native(),
+                    redeem_denom: None,
+                    smart_contract: Some(mock_pair_contract()),
+                    migrate_to_token: None,
+                    shade_protocol_router_path: None,
+                    protocol: None,
+                    sienna_swap_router_path: None,
+                    token_out: None,
+                    minimum_output: None,
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: hops.clone(),
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        let mut handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: transaction_amount,
+        };
+        let mut handle_result = handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg);
+        // == * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Route must be at least 1 hop.")
+        );
+
+        // = when expected token is a snip20
+        hops.push_back(Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_pair_contract_two()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        hops.push_back(Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: None,
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    from_token: mock_token_snip20(),
+                    redeem_denom: None,
+                    smart_contract: Some(mock_pair_contract()),
+                    migrate_to_token: None,
+                    shade_protocol_router_path: None,
+                    protocol: None,
+                    sienna_swap_router_path: None,
+                    token_out: None,
+                    minimum_output: None,
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: hops.clone(),
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        // == when not from receiving hop pair contract
+        handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: None,
+            amount: transaction_amount,
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // == * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+        // == when from receiving hop pair contract
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: transaction_amount,
+        };
+        // === when not expected token
+        handle_result = handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            handle_msg.clone(),
+        );
+        // === * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Received crypto type or amount is wrong.")
+        );
+        // === when expected token
+        // ==== when there are more hops after the next hop
+        // ==== * it sends the messages from hop_messages
+        let next_hop: Hop = hops.pop_front().unwrap();
+        let env = mock_env(mock_sscrt().address, &[]);
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        let mut handle_result_unwrapped = handle_result.unwrap();
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            hop_messages(next_hop.clone(), transaction_amount, &env).unwrap()
+        );
+        // ==== * it stores the updated route state
+        let route_state = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, next_hop);
+        assert_eq!(
+            route_state.remaining_route,
+            Route {
+                hops: hops.clone(),
+                estimated_amount,
+                minimum_acceptable_amount,
+                to: mock_user_address(),
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            },
+        );
+        // ==== when this is the last hop
+        // ===== when the amount is less than the minimum_acceptable_amount
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract_two().address,
+            msg: None,
+            amount: (minimum_acceptable_amount - Uint128(1)).unwrap(),
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // ===== * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Operation fell short of minimum_acceptable_amount")
+        );
+        // ===== when last token is a snip20
+        // ====== when the amount is equal to or less than the estimated amount
+        // ====== * it transfers the received amount to the user
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract_two().address,
+            msg: None,
+            amount: (estimated_amount - Uint128(1)).unwrap(),
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        handle_result_unwrapped = handle_result.unwrap();
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![snip20::send_msg(
+                route_state.remaining_route.to,
+                (estimated_amount - Uint128(1)).unwrap(),
+                None,
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap()]
+        );
+        // ====== * it logs the fee and the payout
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_sscrt().address),
+                log("fee_amount", "0"),
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_sscrt().address),
+                log(
+                    "return_amount",
+                    (estimated_amount - Uint128(1)).unwrap().to_string()
+                )
+            ]
+        );
+
+        // ===== when the amount is greater than the estimated amount
+        // ===== * it sends any excess to the admin
+        // ===== * it transfers the received amount - the excess amount to the user
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    from_token: mock_token_snip20(),
+                    redeem_denom: None,
+                    smart_contract: Some(mock_pair_contract_two()),
+                    migrate_to_token: None,
+                    shade_protocol_router_path: None,
+                    protocol: None,
+                    sienna_swap_router_path: None,
+                    token_out: None,
+                    minimum_output: None,
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: hops.clone(),
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract_two().address,
+            msg: None,
+            amount: estimated_amount + estimated_amount,
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        handle_result_unwrapped = handle_result.unwrap();
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![
+                snip20::transfer_msg(
+                    mock_contract_initiator_address(),
+                    estimated_amount,
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+                snip20::send_msg(
+                    mock_user_address(),
+                    estimated_amount,
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+            ]
+        );
+        // ====== * it logs the return amount
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_sscrt().address),
+                log("fee_amount", "10000000"),
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_sscrt().address),
+                log("return_amount", estimated_amount.to_string())
+            ]
+        );
+        // ===== when last token is a native token
+        hops = VecDeque::new();
+        hops.push_back(Hop {
+            from_token: mock_token_native(),
+            redeem_denom: None,
+            smart_contract: None,
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    from_token: mock_token_snip20(),
+                    redeem_denom: Some(mock_denom()),
+                    smart_contract: None,
+                    migrate_to_token: None,
+                    shade_protocol_router_path: None,
+                    protocol: None,
+                    sienna_swap_router_path: None,
+                    token_out: None,
+                    minimum_output: None,
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: hops.clone(),
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        // ====== when the amount is equal to or less than the estimated amount
+        // ====== * it transfers the received amount to the user
+        handle_msg = HandleMsg::Receive {
+            from: env.contract.address.clone(),
+            msg: None,
+            amount: estimated_amount,
+        };
+        handle_result = handle(
+            &mut deps,
+            mock_env(
+                env.contract.address.clone(),
+                &[Coin {
+                    amount: estimated_amount,
+                    denom: mock_denom(),
+                }],
+            ),
+            handle_msg,
+        );
+        handle_result_unwrapped = handle_result.unwrap();
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: env.contract.address.clone(),
+                to_address: mock_user_address(),
+                amount: vec![Coin {
+                    amount: estimated_amount,
+                    denom: mock_denom(),
+                }],
+            })]
+        );
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_sscrt().address),
+                log("fee_amount", "0"),
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_sscrt().address),
+                log("return_amount", estimated_amount.to_string())
+            ]
+        );
+        // ===== when the amount is greater than the estimated amount
+        // ===== * it sends any excess to the admin
+        // ===== * it transfers the received amount - the excess amount to the user
+        hops = VecDeque::new();
+        hops.push_back(Hop {
+            from_token: mock_token_native(),
+            redeem_denom: None,
+            smart_contract: None,
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    from_token: mock_token_snip20(),
+                    redeem_denom: Some(mock_denom()),
+                    smart_contract: None,
+                    migrate_to_token: None,
+                    shade_protocol_router_path: None,
+                    protocol: None,
+                    sienna_swap_router_path: None,
+                    token_out: None,
+                    minimum_output: None,
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: hops.clone(),
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        handle_msg = HandleMsg::Receive {
+            from: env.contract.address.clone(),
+            msg: None,
+            amount: estimated_amount + estimated_amount,
+        };
+        handle_result = handle(
+            &mut deps,
+            mock_env(
+                env.contract.address.clone(),
+                &[Coin {
+                    amount: estimated_amount + estimated_amount,
+                    denom: mock_denom(),
+                }],
+            ),
+            handle_msg,
+        );
+        handle_result_unwrapped = handle_result.unwrap();
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: env.contract.address.clone(),
+                    to_address: mock_contract_initiator_address(),
+                    amount: vec![Coin {
+                        amount: estimated_amount,
+                        denom: mock_denom(),
+                    }],
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: env.contract.address.clone(),
+                    to_address: mock_user_address(),
+                    amount: vec![Coin {
+                        amount: estimated_amount,
+                        denom: mock_denom(),
+                    }],
+                })
+            ]
+        );
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_sscrt().address),
+                log("fee_amount", "10000000"),
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_sscrt().address),
+                log("return_amount", estimated_amount.to_string())
+            ]
+        );
+
+        // when the current hop is through the SiennaSwap router
+        hops = VecDeque::new();
+        hops.push_back(Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: None,
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        });
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    from_token: Token::Snip20(mock_button()),
+                    redeem_denom: None,
+                    smart_contract: Some(mock_sienna_swap_router()),
+                    migrate_to_token: None,
+                    shade_protocol_router_path: None,
+                    protocol: Some(Protocol::SiennaSwap),
+                    sienna_swap_router_path: Some(vec![]),
+                    token_out: None,
+                    minimum_output: None,
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops: hops.clone(),
+                    estimated_amount: estimated_amount,
+                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        // = when not from the router
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: estimated_amount,
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+        // = when from the router
+        handle_msg = HandleMsg::Receive {
+            from: mock_sienna_swap_router().address,
+            msg: None,
+            amount: estimated_amount,
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // = * it transfers the received amount to the user
+        assert_eq!(
+            handle_result.unwrap().messages,
+            vec![snip20::send_msg(
+                mock_user_address(),
+                estimated_amount,
+                None,
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap()]
+        );
+        // when the current hop has a minimum_output
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(
+            mock_token_snip20(),
+            Some(mock_pair_contract_two()),
+        ));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: Hop {
+                    minimum_output: Some(estimated_amount),
+                    ..swap_hop(Token::Snip20(mock_button()), Some(mock_pair_contract()))
+                },
+                hop_index: 1,
+                remaining_route: Route {
+                    hops,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+        // = when the hop returns less than its minimum_output
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: (estimated_amount - Uint128(1)).unwrap(),
+        };
+        handle_result = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            handle_msg.clone(),
+        );
+        // = * it raises an error naming the hop
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err(format!(
+                "Hop through {} returned 9999999 which is less than its minimum_output of 10000000.",
+                mock_pair_contract().address
+            ))
+        );
+        // = when the hop returns at least its minimum_output
+        handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: estimated_amount,
+        };
+        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // = * it continues to the next hop
+        assert!(handle_result.is_ok());
+    }
+
+    #[test]
+    fn test_handle_hop_with_zap_in() {
+        let mut deps = mock_dependencies_with_wasm_querier();
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: Some(false),
+                wrapped_tokens: None,
+            },
+        )
+        .unwrap();
+        let env = mock_env(mock_sscrt().address, &[]);
+        let minimum_acceptable_amount: Uint128 = Uint128(1_000_000);
+        let estimated_amount: Uint128 = Uint128(10_000_000);
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), None));
+        store_route_state(
+            &mut deps.storage,
+            &RouteState {
+                current_hop: swap_hop(Token::Snip20(mock_button()), Some(mock_pair_contract())),
+                hop_index: 1,
+                remaining_route: Route {
+                    hops,
+                    estimated_amount,
+                    minimum_acceptable_amount,
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: Some(mock_zap_in()),
+                    min_profit: None,
+                    private: None,
+                },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
+            },
+        )
+        .unwrap();
+
+        // when the route's output is received
+        let handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: minimum_acceptable_amount,
+        };
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // * it swaps half of the output through the zap in pair
+        let zap_in_swap_hop: Hop = swap_hop(mock_token_snip20(), Some(mock_zap_in().pair));
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            hop_messages(zap_in_swap_hop.clone(), Uint128(500_000), &env).unwrap()
+        );
+        // * it logs the fee and the zap in swap
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_sscrt().address),
+                log("fee_amount", "0"),
+                log("hop_index", "1"),
+                log("hop_kind", "secret_swap"),
+                log("hop_token_in", mock_sscrt().address),
+                log("hop_token_out", mock_button().address),
+                log("hop_amount_in", "500000"),
+            ]
+        );
+        // * it stores the other token of the pair as the next hop and the retained amount
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, zap_in_swap_hop);
+        assert_eq!(
+            route_state.remaining_route.hops,
+            vec![swap_hop(Token::Snip20(mock_button()), None)]
+                .into_iter()
+                .collect::<VecDeque<Hop>>()
+        );
+        assert_eq!(route_state.zap_in_retained_amount, Some(Uint128(500_000)));
+
+        // when the other token is received from the zap in pair
+        let handle_msg = HandleMsg::Receive {
+            from: mock_zap_in().pair.address,
+            msg: None,
+            amount: Uint128(900_000),
+        };
+        let handle_result_unwrapped =
+            handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg).unwrap();
+        // * it provides liquidity in the pool's ratio, sends the minted liquidity token to the user and refunds the rest
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![
+                snip20::increase_allowance_msg(
+                    mock_zap_in().pair.address,
+                    Uint128(450_000),
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+                snip20::increase_allowance_msg(
+                    mock_zap_in().pair.address,
+                    Uint128(900_000),
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_button().contract_hash,
+                    mock_button().address,
+                )
+                .unwrap(),
+                self_msg(
+                    &mock_env(mock_button().address, &[]),
+                    &HandleMsg::RecordBalance {
+                        token: mock_zap_in().liquidity_token,
+                    },
+                )
+                .unwrap(),
+                SecretSwapPair::ProvideLiquidity {
+                    assets: [
+                        Asset {
+                            info: mock_asset_info(mock_sscrt()),
+                            amount: Uint128(450_000),
+                        },
+                        Asset {
+                            info: mock_asset_info(mock_button()),
+                            amount: Uint128(900_000),
+                        },
+                    ],
+                    slippage_tolerance: None,
+                }
+                .to_cosmos_msg(
+                    mock_zap_in().pair.contract_hash,
+                    mock_zap_in().pair.address,
+                    None
+                )
+                .unwrap(),
+                self_msg(
+                    &mock_env(mock_button().address, &[]),
+                    &HandleMsg::SendMintedBalance {
+                        token: mock_zap_in().liquidity_token,
+                    },
+                )
+                .unwrap(),
+                snip20::send_msg(
+                    mock_user_address(),
+                    Uint128(50_000),
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+            ]
+        );
+        // * it completes the route and keeps the route's output token as the current hop
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert!(route_state.remaining_route.hops.is_empty());
+        assert_eq!(route_state.current_hop, zap_in_swap_hop);
+        assert_eq!(route_state.zap_in_retained_amount, None);
+    }
+
+    #[test]
+    fn test_handle_hop_with_zap_out() {
+        let mut deps = mock_dependencies_with_wasm_querier();
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: Some(false),
+                wrapped_tokens: None,
+            },
+        )
+        .unwrap();
+        let liquidity_token: SecretContract = mock_zap_in().liquidity_token;
+        let pair: SecretContract = mock_zap_in().pair;
+        let withdraw_liquidity_hop: Hop = Hop {
+            protocol: Some(Protocol::WithdrawLiquidity),
+            token_out: Some(mock_button()),
+            ..swap_hop(Token::Snip20(liquidity_token.clone()), Some(pair.clone()))
+        };
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(withdraw_liquidity_hop.clone());
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let mut route: Route = Route {
+            hops,
+            estimated_amount: Uint128(10_000_000),
+            minimum_acceptable_amount: Uint128(1_000_000),
+            to: mock_user_address(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: None,
+            private: None,
+        };
+
+        // when the withdraw liquidity hop isn't the first hop
+        let mut invalid_route: Route = route.clone();
+        invalid_route
+            .hops
+            .push_front(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(to_binary(&invalid_route).unwrap()),
+            amount: Uint128(1_000),
+        };
+        let handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Withdraw liquidity must be the first hop.")
+        );
+
+        // when the withdraw liquidity hop doesn't set token_out
+        let mut invalid_route: Route = route.clone();
+        invalid_route.hops[0].token_out = None;
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(to_binary(&invalid_route).unwrap()),
+            amount: Uint128(1_000),
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env(liquidity_token.address.clone(), &[]),
+            handle_msg,
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Withdraw liquidity hop must set token_out.")
+        );
+
+        // when token_out isn't in the pair
+        route.hops[0].token_out = Some(mock_token());
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(to_binary(&route).unwrap()),
+            amount: Uint128(1_000),
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env(liquidity_token.address.clone(), &[]),
+            handle_msg,
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Token is not in the pair.")
+        );
+
+        // when the liquidity token is received
+        route.hops[0].token_out = Some(mock_button());
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(to_binary(&route).unwrap()),
+            amount: Uint128(1_000),
+        };
+        let env = mock_env(liquidity_token.address.clone(), &[]);
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // * it sends the liquidity token to the pair to withdraw liquidity
+        assert_eq!(
+            handle_result_unwrapped.messages[0],
+            hop_messages(withdraw_liquidity_hop.clone(), Uint128(1_000), &env).unwrap()[0]
+        );
+        // * it expects both tokens of the pair in the pool's order before the rest of the route
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        let withdrawn_sscrt_hop: Hop = Hop {
+            protocol: Some(Protocol::WithdrawLiquidity),
+            token_out: Some(mock_button()),
+            ..swap_hop(mock_token_snip20(), Some(pair.clone()))
+        };
+        let withdrawn_button_hop: Hop = Hop {
+            protocol: Some(Protocol::WithdrawLiquidity),
+            token_out: Some(mock_button()),
+            ..swap_hop(Token::Snip20(mock_button()), Some(pair.clone()))
+        };
+        assert_eq!(
+            route_state.remaining_route.hops,
+            vec![
+                withdrawn_sscrt_hop.clone(),
+                withdrawn_button_hop,
+                swap_hop(Token::Snip20(mock_button()), None)
+            ]
+            .into_iter()
+            .collect::<VecDeque<Hop>>()
+        );
+
+        // when the first withdrawn token is received from someone other than the pair
+        let handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: Uint128(500_000),
+        };
+        let handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
+        // * it raises an Unauthorized error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when the first withdrawn token is received from the pair
+        let handle_msg = HandleMsg::Receive {
+            from: pair.address.clone(),
+            msg: None,
+            amount: Uint128(500_000),
+        };
+        let handle_result_unwrapped =
+            handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        // * it waits for the other withdrawn token
+        assert!(handle_result_unwrapped.messages.is_empty());
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, withdrawn_sscrt_hop);
+        assert_eq!(route_state.zap_out_retained_amount, Some(Uint128(500_000)));
+
+        // when the second withdrawn token is received from the pair
+        let handle_msg = HandleMsg::Receive {
+            from: pair.address.clone(),
+            msg: None,
+            amount: Uint128(1_000_000),
+        };
+        let env = mock_env(mock_button().address, &[]);
+        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
+        // * it swaps the token that isn't token_out through the pair
+        let zap_out_swap_hop: Hop = swap_hop(mock_token_snip20(), Some(pair.clone()));
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            hop_messages(zap_out_swap_hop.clone(), Uint128(500_000), &env).unwrap()
+        );
+        // * it retains the withdrawn token_out
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, zap_out_swap_hop);
+        assert_eq!(
+            route_state.zap_out_retained_amount,
+            Some(Uint128(1_000_000))
+        );
+
+        // when the swapped token is received from the pair
+        let handle_msg = HandleMsg::Receive {
+            from: pair.address,
+            msg: None,
+            amount: Uint128(900_000),
+        };
+        let handle_result_unwrapped = handle(&mut deps, env, handle_msg).unwrap();
+        // * it sends the swapped and retained amounts to the user
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![snip20::send_msg(
+                mock_user_address(),
+                Uint128(1_900_000),
+                None,
+                None,
+                BLOCK_SIZE,
+                mock_button().contract_hash,
+                mock_button().address,
+            )
+            .unwrap()]
+        );
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("fee_token", mock_button().address),
+                log("fee_amount", "0"),
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_button().address),
+                log("return_amount", Uint128(1_900_000).to_string())
+            ]
+        );
+        // * it returns the swap result
+        assert_eq!(
+            from_binary::<SwapResult>(&handle_result_unwrapped.data.unwrap()).unwrap(),
+            SwapResult {
+                return_amount: Uint128(1_900_000),
+                fee_amount: Uint128::zero(),
+                output_token: mock_button().address,
+                hops_executed: 2,
+            }
+        );
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert!(route_state.remaining_route.hops.is_empty());
+        assert_eq!(route_state.zap_out_retained_amount, None);
+    }
+
+    #[test]
+    fn test_hop_messages() {
+        let env = mock_env(mock_user_address(), &[]);
+        let amount = Uint128(555);
+        let shade_protocol_router_path: Option<Vec<SecretContractForShadeProtocol>> =
+            Some(vec![SecretContractForShadeProtocol {
+                addr: mock_pair_contract().address.to_string(),
+                code_hash: mock_pair_contract().contract_hash,
+            }]);
+        let mut hop: Hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_shade_protocol_router()),
+            migrate_to_token: None,
+            shade_protocol_router_path,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        // when hop.from_token == Token::Snip20
+        // = when shade_protocol_router_path is present
+        // = * it sends the snip 20 to the hop smart contract with the SwapTokensForExact struct and path
+        let mut messages: Vec<CosmosMsg> = hop_messages(hop.clone(), amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                hop.smart_contract.unwrap().address,
+                amount,
+                // build swap msg for the next hop
+                Some(
+                    to_binary(&ShadeProtocol::SwapTokensForExact {
+                        // set the recepient of the swap to be this contract (the router)
+                        path: hop.shade_protocol_router_path.unwrap(),
+                    })
+                    .unwrap()
+                ),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap()]
+        );
+        // = when migrate_to_token is present
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_shade_protocol_router()),
+            migrate_to_token: Some(mock_button()),
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        // = * it sends the snip 20 to the hop smart contract and then it sends the migrate_to_token to itself
+        messages = hop_messages(hop.clone(), amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                snip20::send_msg(
+                    hop.smart_contract.unwrap().address,
+                    amount,
+                    // build swap msg for the next hop
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+                snip20::send_msg(
+                    env.contract.address.clone(),
+                    amount,
+                    // build swap msg for the next hop
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_button().contract_hash,
+                    mock_button().address,
+                )
+                .unwrap(),
+            ]
+        );
+        // = when redeem_denom is present
+        // = * it unwraps the token and then sends the native token to itself with a receive message
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: Some(mock_denom()),
+            smart_contract: None,
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                snip20::redeem_msg(
+                    amount,
+                    Some(mock_denom()),
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: env.contract.address.clone(),
+                    callback_code_hash: env.contract_code_hash.clone(),
+                    msg: to_binary(&HandleMsg::Receive {
+                        from: env.contract.address.clone(),
+                        msg: None,
+                        amount,
+                    })
+                    .unwrap(),
+                    send: vec![Coin {
+                        amount,
+                        denom: mock_denom()
+                    }],
+                }),
+            ]
+        );
+        // = when only smart_contract is present
+        // = It sends a swap request to specificed smart contract
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_pair_contract()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                mock_pair_contract().address,
+                amount,
+                Some(
+                    to_binary(&Snip20Swap::Swap {
+                        // set expected_return to None because we don't care about slippage mid-route
+                        expected_return: None,
+                        // set the recepient of the swap to be this contract (the router)
+                        to: Some(env.contract.address.clone()),
+                    })
+                    .unwrap()
+                ),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap(),]
+        );
+        // == when minimum_output is present
+        // == * it sends minimum_output as the expected_return
+        hop = Hop {
+            minimum_output: Some(Uint128(900)),
+            ..swap_hop(mock_token_snip20(), Some(mock_pair_contract()))
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                mock_pair_contract().address,
+                amount,
+                Some(
+                    to_binary(&Snip20Swap::Swap {
+                        expected_return: Some(Uint128(900)),
+                        to: Some(env.contract.address.clone()),
+                    })
+                    .unwrap()
+                ),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap(),]
+        );
+        // == when the protocol is ShadeSwap
+        // == * it sends a swap request in the ShadeSwap pair format
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_pair_contract()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: Some(Protocol::ShadeSwap),
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                mock_pair_contract().address,
+                amount,
+                Some(
+                    to_binary(&ShadeProtocolPair::SwapTokens {
+                        expected_return: None,
+                        to: Some(env.contract.address.to_string()),
+                    })
+                    .unwrap()
+                ),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap(),]
+        );
+        // == when the protocol is SiennaSwap
+        // == * it sends a swap request in the SiennaSwap pair format
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_pair_contract()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: Some(Protocol::SiennaSwap),
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                mock_pair_contract().address,
+                amount,
+                Some(
+                    to_binary(&SiennaSwapPair::Swap {
+                        expected_return: None,
+                        to: Some(env.contract.address.clone()),
+                    })
+                    .unwrap()
+                ),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap(),]
+        );
+        // = when sienna_swap_router_path is present
+        // = * it sends the snip 20 to the SiennaSwap router with the route back to this contract
+        let sienna_swap_router_path: Vec<SiennaSwapHop> = vec![SiennaSwapHop {
+            from_token: SiennaSwapTokenType::CustomToken {
+                contract_addr: mock_sscrt().address,
+                token_code_hash: mock_sscrt().contract_hash,
+            },
+            pair_address: mock_pair_contract().address,
+            pair_code_hash: mock_pair_contract().contract_hash,
+        }];
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: None,
+            smart_contract: Some(mock_sienna_swap_router()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: Some(Protocol::SiennaSwap),
+            sienna_swap_router_path: Some(sienna_swap_router_path.clone()),
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                mock_sienna_swap_router().address,
+                amount,
+                Some(
+                    to_binary(&SiennaSwapRoute {
+                        hops: sienna_swap_router_path,
+                        expected_return: None,
+                        to: env.contract.address.clone(),
+                    })
+                    .unwrap()
+                ),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap(),]
+        );
+        // = when the protocol is StakingDerivative
+        // = * it unwraps the token, stakes it and then sends the minted derivative to itself
+        hop = Hop {
+            from_token: mock_token_snip20(),
+            redeem_denom: Some(mock_denom()),
+            smart_contract: Some(mock_staking_derivative()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: Some(Protocol::StakingDerivative),
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        let staking_messages: Vec<CosmosMsg> = vec![
+            self_msg(
+                &env,
+                &HandleMsg::RecordBalance {
+                    token: mock_staking_derivative(),
+                },
+            )
+            .unwrap(),
+            StakingDerivative::Stake {}
+                .to_cosmos_msg(
+                    BLOCK_SIZE,
+                    mock_staking_derivative().contract_hash,
+                    mock_staking_derivative().address,
+                    Some(Coin {
+                        amount,
+                        denom: mock_denom(),
+                    }),
+                )
+                .unwrap(),
+            self_msg(
+                &env,
+                &HandleMsg::SendMintedBalance {
+                    token: mock_staking_derivative(),
+                },
+            )
+            .unwrap(),
+        ];
+        assert_eq!(
+            messages,
+            [
+                vec![snip20::redeem_msg(
+                    amount,
+                    Some(mock_denom()),
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap()],
+                staking_messages.clone(),
+            ]
+            .concat()
+        );
+        // = when the protocol is WithdrawLiquidity
+        // = * it sends the liquidity token to the pair to withdraw liquidity
+        hop = Hop {
+            from_token: Token::Snip20(mock_zap_in().liquidity_token),
+            redeem_denom: None,
+            smart_contract: Some(mock_zap_in().pair),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: Some(Protocol::WithdrawLiquidity),
+            sienna_swap_router_path: None,
+            token_out: Some(mock_button()),
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![snip20::send_msg(
+                mock_zap_in().pair.address,
+                amount,
+                Some(to_binary(&Snip20Swap::WithdrawLiquidity {}).unwrap()),
+                None,
+                BLOCK_SIZE,
+                mock_zap_in().liquidity_token.contract_hash,
+                mock_zap_in().liquidity_token.address,
+            )
+            .unwrap(),]
+        );
+        // when hop.from_token == Token::Native
+        // = when the protocol is StakingDerivative
+        // = * it stakes the native token and then sends the minted derivative to itself
+        hop = Hop {
+            from_token: mock_token_native(),
+            redeem_denom: Some(mock_denom()),
+            smart_contract: Some(mock_staking_derivative()),
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: Some(Protocol::StakingDerivative),
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(messages, staking_messages);
+        // = when the protocol isn't StakingDerivative
+        // = * it wraps the contract then sends it to itself
+        hop = Hop {
+            from_token: mock_token_native(),
+            redeem_denom: Some(mock_denom()),
+            smart_contract: None,
+            migrate_to_token: None,
+            shade_protocol_router_path: None,
+            protocol: None,
+            sienna_swap_router_path: None,
+            token_out: None,
+            minimum_output: None,
+        };
+        messages = hop_messages(hop, amount, &env).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                Snip20::Deposit { padding: None }
+                    .to_cosmos_msg(
+                        BLOCK_SIZE,
+                        mock_sscrt().contract_hash,
+                        mock_sscrt().address,
+                        Some(Coin {
+                            amount,
+                            denom: mock_denom(),
+                        }),
+                    )
+                    .unwrap(),
+                snip20::send_msg(
+                    env.contract.address.clone(),
+                    amount,
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limit_orders() {
+        let (_init_result, mut deps) = init_helper();
+        let amount: Uint128 = Uint128(1_000_000);
+        let min_output: Uint128 = Uint128(1_900_000);
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let route_template: Route = Route {
+            hops: hops.clone(),
+            estimated_amount: Uint128(2_000_000),
+            minimum_acceptable_amount: Uint128(1_800_000),
+            to: mock_user_address(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: None,
+            private: None,
+        };
+        let place_limit_order_msg =
+            |route_template: Route, expiration: Option<u64>| HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&ReceiveMsg::PlaceLimitOrder {
+                        route_template,
+                        min_output,
+                        expiration,
+                    })
+                    .unwrap(),
+                ),
+                amount,
+            };
+
+        // when placing an order for someone else
+        let mut invalid_route_template: Route = route_template.clone();
+        invalid_route_template.to = mock_merchant_address();
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            place_limit_order_msg(invalid_route_template, None),
+        );
+        // * it raises an Unauthorized error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when placing an order with the input token
+        let handle_result_unwrapped = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            place_limit_order_msg(route_template.clone(), None),
+        )
+        .unwrap();
+        // * it escrows the input and logs the order id
+        assert!(handle_result_unwrapped.messages.is_empty());
+        assert_eq!(handle_result_unwrapped.log, vec![log("order_id", "0")]);
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, &mock_sscrt().address.0).unwrap(),
+            amount
+        );
+        let order: LimitOrder = LimitOrder {
+            id: 0,
+            owner: mock_user_address(),
+            route_template: route_template.clone(),
+            amount,
+            denom: None,
+            min_output,
+            expiration: None,
+            trigger: None,
+        };
+        // * it can be queried by the owner's address and viewing key
+        handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            place_limit_order_msg(route_template.clone(), Some(1)),
+        )
+        .unwrap();
+        handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        let query_result = query(
+            &deps,
+            QueryMsg::Orders {
+                address: mock_user_address(),
+                key: "wrong key".to_string(),
+                page: 0,
+                page_size: 1,
             },
-            remaining_route: Route {
-                hops: hops,
-                estimated_amount: Uint128(1_000_000),
-                minimum_acceptable_amount: Uint128(1_000_000),
-                to: mock_user_address(),
+        );
+        assert_eq!(
+            query_result.unwrap_err(),
+            StdError::generic_err("Wrong viewing key for this address or viewing key not set.")
+        );
+        let query_result = query(
+            &deps,
+            QueryMsg::Orders {
+                address: mock_user_address(),
+                key: "key".to_string(),
+                page: 0,
+                page_size: 1,
             },
-        };
-        store_route_state(&mut deps.storage, &route_state).unwrap();
-        // == when it isn't called by the contract
-        // == * it raises an error
-        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        )
+        .unwrap();
+        let orders: Vec<LimitOrder> = from_binary(&query_result).unwrap();
+        assert_eq!(orders, vec![order.clone()]);
+        let query_result = query(
+            &deps,
+            QueryMsg::Orders {
+                address: mock_user_address(),
+                key: "key".to_string(),
+                page: 1,
+                page_size: 1,
+            },
+        )
+        .unwrap();
+        let orders: Vec<LimitOrder> = from_binary(&query_result).unwrap();
+        assert_eq!(orders[0].id, 1);
+
+        // when executing an order that doesn't exist
+        let env = mock_env(mock_merchant_address(), &[]);
+        let handle_result = handle(
+            &mut deps,
+            env.clone(),
+            HandleMsg::ExecuteOrder {
+                id: 2,
+                route: route_template.clone(),
+            },
+        );
+        // * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
-            StdError::Unauthorized { backtrace: None }
+            StdError::generic_err("Order not found.")
         );
-        // == when it's called by the contract
-        // == * it raises an error
+
+        // when executing an order that has expired
         let handle_result = handle(
             &mut deps,
-            mock_env(mock_contract().address, &[]),
-            handle_msg.clone(),
+            env.clone(),
+            HandleMsg::ExecuteOrder {
+                id: 1,
+                route: route_template.clone(),
+            },
         );
+        // * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
-            StdError::generic_err(format!(
-                "cannot finalize: route still contains hops: {:?}",
-                route_state.remaining_route
-            ))
+            StdError::generic_err("Order has expired.")
         );
 
-        // = when there are no hops
-        let hops: VecDeque<Hop> = VecDeque::new();
-        let route_state: RouteState = RouteState {
-            current_hop: Hop {
-                from_token: mock_token_native(),
-                redeem_denom: None,
-                smart_contract: Some(mock_pair_contract()),
-                migrate_to_token: None,
-                shade_protocol_router_path: None,
-            },
-            remaining_route: Route {
-                hops: hops,
-                estimated_amount: Uint128(1_000_000),
-                minimum_acceptable_amount: Uint128(1_000_000),
-                to: mock_user_address(),
+        // when executing an order with a route that ends with another token
+        let mut invalid_route: Route = route_template.clone();
+        invalid_route.hops[1] = swap_hop(Token::Snip20(mock_token()), None);
+        let handle_result = handle(
+            &mut deps,
+            env.clone(),
+            HandleMsg::ExecuteOrder {
+                id: 0,
+                route: invalid_route,
             },
-        };
-        store_route_state(&mut deps.storage, &route_state).unwrap();
-        // == when it isn't called by the contract
-        // == * it raises an error
-        let handle_result = handle(&mut deps, env.clone(), handle_msg.clone());
+        );
+        // * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
-            StdError::Unauthorized { backtrace: None }
+            StdError::generic_err("Route must start and end with the order's tokens.")
         );
-        // == when it's called by the contract
-        // == * it returns an Ok response
-        handle(
+
+        // when executing an order with a route that starts and ends with the order's tokens
+        let handle_result_unwrapped = handle(
             &mut deps,
-            mock_env(mock_contract().address, &[]),
-            handle_msg.clone(),
+            env.clone(),
+            HandleMsg::ExecuteOrder {
+                id: 0,
+                route: route_template.clone(),
+            },
         )
         .unwrap();
-        let handle_result = handle(
-            &mut deps,
-            mock_env(mock_contract().address, &[]),
-            handle_msg,
+        // * it sends the escrowed input along the route and then finalizes the route
+        let mut messages = hop_messages(hops[0].clone(), amount, &env).unwrap();
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: mock_contract().address,
+            callback_code_hash: mock_contract().contract_hash,
+            msg: to_binary(&HandleMsg::FinalizeRoute {}).unwrap(),
+            send: vec![],
+        }));
+        assert_eq!(handle_result_unwrapped.messages, messages);
+        // * it stores the route state with the order's minimum and the keeper
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.remaining_route.minimum_acceptable_amount,
+            min_output
         );
+        assert_eq!(route_state.keeper, Some(mock_merchant_address()));
+        // * it removes the order and its escrow
+        assert_eq!(read_order(&deps.storage, 0).unwrap(), None);
         assert_eq!(
-            handle_result.unwrap_err(),
-            StdError::generic_err("no route to finalize")
+            read_user_order_ids(&deps.storage, &mock_user_address()).unwrap(),
+            vec![1]
         );
-    }
-
-    #[test]
-    fn test_handle_first_hop() {
-        let (_init_result, mut deps) = init_helper();
-        let minimum_acceptable_amount: Uint128 = Uint128(1_000_000);
-        let estimated_amount: Uint128 = Uint128(10_000_000);
-        let transaction_amount: Uint128 = minimum_acceptable_amount;
-        let env = mock_env(
-            mock_user_address(),
-            &[Coin {
-                denom: mock_denom(),
-                amount: transaction_amount,
-            }],
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, &mock_sscrt().address.0).unwrap(),
+            amount
         );
 
-        // when there is less than 2 hops
-        let mut hops: VecDeque<Hop> = VecDeque::new();
-        hops.push_back(Hop {
-            from_token: mock_token_native(),
-            redeem_denom: Some(mock_denom()),
-            smart_contract: Some(mock_pair_contract()),
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
+        // = when the output less the keeper fee is below min_output
         let handle_msg = HandleMsg::Receive {
-            from: mock_user_address(),
-            msg: Some(
-                to_binary(&Route {
-                    hops: hops.clone(),
-                    to: mock_user_address(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
-                })
-                .unwrap(),
-            ),
-            amount: transaction_amount,
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: Uint128(1_900_000),
         };
-        let handle_result = handle(&mut deps, env.clone(), handle_msg);
-        // * it raises an error
+        let handle_result = handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg);
+        // = * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
-            StdError::generic_err("Route must have at least 2 hops.")
+            StdError::generic_err("Operation fell short of minimum_acceptable_amount")
         );
-
-        // when there is 2 or more hops
-        // = when the amount specified does match the amount sent in
-        hops.push_back(Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: None,
-            smart_contract: Some(mock_pair_contract_two()),
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
+        // = when the output less the keeper fee reaches min_output
         let handle_msg = HandleMsg::Receive {
-            from: mock_user_address(),
-            msg: Some(
-                to_binary(&Route {
-                    hops: hops.clone(),
-                    to: mock_user_address(),
-                    estimated_amount,
-                    minimum_acceptable_amount,
-                })
-                .unwrap(),
-            ),
-            amount: transaction_amount + transaction_amount,
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: Uint128(2_000_000),
         };
-        let handle_result = handle(&mut deps, env.clone(), handle_msg);
-        // == * it raises an error
+        let handle_result_unwrapped =
+            handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg).unwrap();
+        // = * it pays the keeper fee and sends the rest to the owner
         assert_eq!(
-            handle_result.unwrap_err(),
-            StdError::generic_err("Received crypto type or amount is wrong.")
-        );
-        // = when the amount specified matches the amount sent in
-        // == when the to does not match the user
-        let handle_msg = HandleMsg::Receive {
-            from: mock_user_address(),
-            msg: Some(
-                to_binary(&Route {
-                    hops: hops.clone(),
-                    to: mock_pair_contract().address,
-                    estimated_amount,
-                    minimum_acceptable_amount,
-                })
+            handle_result_unwrapped.messages,
+            vec![
+                snip20::transfer_msg(
+                    mock_merchant_address(),
+                    Uint128(2_000),
+                    None,
+                    BLOCK_SIZE,
+                    mock_button().contract_hash,
+                    mock_button().address,
+                )
                 .unwrap(),
-            ),
-            amount: transaction_amount,
-        };
-        let handle_result = handle(&mut deps, env.clone(), handle_msg);
-        // == * it raises an error
+                snip20::send_msg(
+                    mock_user_address(),
+                    Uint128(1_998_000),
+                    None,
+                    None,
+                    BLOCK_SIZE,
+                    mock_button().contract_hash,
+                    mock_button().address,
+                )
+                .unwrap()
+            ]
+        );
+
+        // when cancelling an order as someone other than the owner
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_merchant_address(), &[]),
+            HandleMsg::CancelOrder { id: 1 },
+        );
+        // * it raises an Unauthorized error
         assert_eq!(
             handle_result.unwrap_err(),
             StdError::Unauthorized { backtrace: None }
         );
 
-        // == when the to matches the sender
-        let handle_msg = HandleMsg::Receive {
-            from: mock_user_address(),
-            msg: Some(
-                to_binary(&Route {
-                    hops: hops.clone(),
-                    to: mock_user_address(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
-                })
-                .unwrap(),
-            ),
-            amount: transaction_amount,
+        // when cancelling an order as the owner
+        let handle_result_unwrapped = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::CancelOrder { id: 1 },
+        )
+        .unwrap();
+        // * it refunds the escrowed input and removes the order
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![snip20::transfer_msg(
+                mock_user_address(),
+                amount,
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap()]
+        );
+        assert_eq!(read_order(&deps.storage, 1).unwrap(), None);
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, &mock_sscrt().address.0).unwrap(),
+            Uint128::zero()
+        );
+
+        // when orders are escrowed in two native denoms
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetWrappedTokens {
+                wrapped_tokens: vec![
+                    WrappedToken {
+                        token: mock_sscrt().address,
+                        denom: "uscrt".to_string(),
+                    },
+                    mock_wrapped_token(),
+                ],
+            },
+        )
+        .unwrap();
+        let native_route_template = |denom: String| -> Route {
+            let mut hops: VecDeque<Hop> = VecDeque::new();
+            hops.push_back(Hop {
+                redeem_denom: Some(denom),
+                ..swap_hop(mock_token_native(), None)
+            });
+            hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+            hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+            Route {
+                hops,
+                ..route_template.clone()
+            }
         };
-        let handle_result_unwrapped = handle(&mut deps, env.clone(), handle_msg).unwrap();
-        // == * it stores the route state
-        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
-        assert_eq!(route_state.current_hop, hops.pop_front().unwrap());
+        for denom in &["uscrt".to_string(), mock_denom()] {
+            handle(
+                &mut deps,
+                mock_env(
+                    mock_user_address(),
+                    &[Coin {
+                        denom: denom.clone(),
+                        amount,
+                    }],
+                ),
+                HandleMsg::Receive {
+                    from: mock_user_address(),
+                    msg: Some(
+                        to_binary(&ReceiveMsg::PlaceLimitOrder {
+                            route_template: native_route_template(denom.clone()),
+                            min_output,
+                            expiration: None,
+                        })
+                        .unwrap(),
+                    ),
+                    amount,
+                },
+            )
+            .unwrap();
+        }
+        // = when the keeper's first hop uses the other order's denom
+        let mut invalid_route: Route = native_route_template("uscrt".to_string());
+        invalid_route.hops[0] = Hop {
+            redeem_denom: Some(mock_denom()),
+            protocol: Some(Protocol::StakingDerivative),
+            ..swap_hop(mock_token_native(), Some(mock_staking_derivative()))
+        };
+        let handle_result = handle(
+            &mut deps,
+            env.clone(),
+            HandleMsg::ExecuteOrder {
+                id: 2,
+                route: invalid_route,
+            },
+        );
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Route must start and end with the order's tokens.")
+        );
+        // = * both denoms stay escrowed
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, "uscrt").unwrap(),
+            amount
+        );
+        assert_eq!(
+            read_escrowed_amount(&deps.storage, &mock_denom()).unwrap(),
+            amount
+        );
+        // = when the keeper's first hop stakes into a derivative that isn't whitelisted
+        let mut invalid_route: Route = native_route_template("uscrt".to_string());
+        invalid_route.hops[0] = Hop {
+            redeem_denom: Some("uscrt".to_string()),
+            protocol: Some(Protocol::StakingDerivative),
+            ..swap_hop(mock_token_native(), Some(mock_staking_derivative()))
+        };
+        let handle_result = handle(
+            &mut deps,
+            env,
+            HandleMsg::ExecuteOrder {
+                id: 2,
+                route: invalid_route,
+            },
+        );
+        // = * it raises an error
         assert_eq!(
-            route_state.remaining_route,
-            Route {
-                hops,
-                estimated_amount,
-                minimum_acceptable_amount,
-                to: mock_user_address(),
-            }
+            handle_result.unwrap_err(),
+            StdError::generic_err("Wrapped token isn't whitelisted.")
         );
-        // == * it creates messages based on the first hop and then finalizes the route
-        let mut hop_messages =
-            hop_messages(route_state.current_hop, transaction_amount, &env).unwrap();
-        hop_messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: mock_contract().address,
-            callback_code_hash: mock_contract().contract_hash.clone(),
-            msg: to_binary(&HandleMsg::FinalizeRoute {}).unwrap(),
-            send: vec![],
-        }));
-        assert_eq!(handle_result_unwrapped.messages, hop_messages)
     }
 
     #[test]
-    fn test_handle_hop() {
-        let (_init_result, mut deps) = init_helper();
-        let minimum_acceptable_amount: Uint128 = Uint128(1_000_000);
-        let estimated_amount: Uint128 = Uint128(10_000_000);
-        let transaction_amount: Uint128 = minimum_acceptable_amount;
+    fn test_minted_balance() {
+        let mut deps = mock_dependencies_with_wasm_querier();
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: Some(false),
+                wrapped_tokens: None,
+            },
+        )
+        .unwrap();
+        let contract_env = mock_env(mock_contract().address, &[]);
+        let record_balance_msg = HandleMsg::RecordBalance {
+            token: mock_staking_derivative(),
+        };
+        let send_minted_balance_msg = HandleMsg::SendMintedBalance {
+            token: mock_staking_derivative(),
+        };
 
-        // where there are no hops
+        // when it isn't called by the contract
+        // * it raises an error
+        for handle_msg in [record_balance_msg.clone(), send_minted_balance_msg.clone()] {
+            assert_eq!(
+                handle(&mut deps, mock_env(mock_user_address(), &[]), handle_msg).unwrap_err(),
+                StdError::Unauthorized { backtrace: None }
+            );
+        }
+
+        // when it's called by the contract
+        // = when there isn't a route state
+        // = * it raises an error
+        assert_eq!(
+            handle(&mut deps, contract_env.clone(), record_balance_msg.clone()).unwrap_err(),
+            StdError::generic_err("cannot find route")
+        );
+        // = when there's a route state
         let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(Token::Snip20(mock_staking_derivative()), None));
         store_route_state(
             &mut deps.storage,
             &RouteState {
                 current_hop: Hop {
                     from_token: mock_token_native(),
-                    redeem_denom: None,
-                    smart_contract: Some(mock_pair_contract()),
+                    redeem_denom: Some(mock_denom()),
+                    smart_contract: Some(mock_staking_derivative()),
                     migrate_to_token: None,
                     shade_protocol_router_path: None,
+                    protocol: Some(Protocol::StakingDerivative),
+                    sienna_swap_router_path: None,
+                    token_out: None,
+                    minimum_output: None,
                 },
+                hop_index: 1,
                 remaining_route: Route {
-                    hops: hops.clone(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
+                    hops,
+                    estimated_amount: Uint128(1_000),
+                    minimum_acceptable_amount: Uint128(1_000),
                     to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
                 },
+                zap_in_retained_amount: None,
+                zap_out_retained_amount: None,
+                balance_before_mint: None,
+                keeper: None,
+                oracle_minimum_amount: None,
+                remaining_inputs: VecDeque::new(),
+                collected_amount: None,
+                user: mock_user_address(),
+                swap: Swap::default(),
             },
         )
         .unwrap();
-        let mut handle_msg = HandleMsg::Receive {
-            from: mock_pair_contract().address,
-            msg: None,
-            amount: transaction_amount,
-        };
-        let mut handle_result = handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg);
+        // == when the balance wasn't recorded before minting
         // == * it raises an error
         assert_eq!(
-            handle_result.unwrap_err(),
-            StdError::generic_err("Route must be at least 1 hop.")
+            handle(
+                &mut deps,
+                contract_env.clone(),
+                send_minted_balance_msg.clone()
+            )
+            .unwrap_err(),
+            StdError::generic_err("Balance wasn't recorded before minting.")
         );
-
-        // = when expected token is a snip20
-        hops.push_back(Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: None,
-            smart_contract: Some(mock_pair_contract_two()),
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
-        hops.push_back(Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: None,
-            smart_contract: None,
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
-        store_route_state(
-            &mut deps.storage,
-            &RouteState {
-                current_hop: Hop {
-                    from_token: mock_token_snip20(),
-                    redeem_denom: None,
-                    smart_contract: Some(mock_pair_contract()),
-                    migrate_to_token: None,
-                    shade_protocol_router_path: None,
-                },
-                remaining_route: Route {
-                    hops: hops.clone(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
-                    to: mock_user_address(),
-                },
+        // == when the balance is recorded
+        handle(&mut deps, contract_env.clone(), record_balance_msg).unwrap();
+        // == * it stores this contract's balance of the token
+        let mut route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.balance_before_mint,
+            Some(mock_staking_derivative_balance())
+        );
+        // == when the token has been minted
+        route_state.balance_before_mint = Some(Uint128(600));
+        store_route_state(&mut deps.storage, &route_state).unwrap();
+        let handle_result = handle(&mut deps, contract_env.clone(), send_minted_balance_msg);
+        // == * it sends the increase in its balance to itself
+        assert_eq!(
+            handle_result.unwrap().messages,
+            vec![snip20::send_msg(
+                contract_env.contract.address.clone(),
+                Uint128(400),
+                None,
+                None,
+                BLOCK_SIZE,
+                mock_staking_derivative().contract_hash,
+                mock_staking_derivative().address,
+            )
+            .unwrap()]
+        );
+        let mut route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.balance_before_mint, None);
+        // == when the minted token is the output of a zap in
+        route_state.current_hop = swap_hop(mock_token_snip20(), Some(mock_pair_contract()));
+        route_state.remaining_route.hops = VecDeque::new();
+        route_state.balance_before_mint = Some(Uint128(600));
+        store_route_state(&mut deps.storage, &route_state).unwrap();
+        let handle_result_unwrapped = handle(
+            &mut deps,
+            contract_env.clone(),
+            HandleMsg::SendMintedBalance {
+                token: mock_staking_derivative(),
             },
         )
         .unwrap();
-        // == when not from receiving hop pair contract
-        handle_msg = HandleMsg::Receive {
+        // == * it sends the increase in its balance to the user
+        assert_eq!(
+            handle_result_unwrapped.messages,
+            vec![snip20::send_msg(
+                mock_user_address(),
+                Uint128(400),
+                None,
+                None,
+                BLOCK_SIZE,
+                mock_staking_derivative().contract_hash,
+                mock_staking_derivative().address,
+            )
+            .unwrap()]
+        );
+        // == * it logs the payout
+        assert_eq!(
+            handle_result_unwrapped.log,
+            vec![
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_staking_derivative().address),
+                log("return_amount", "400"),
+            ]
+        );
+        // == * it records the swap
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(
+            route_state.swap.output,
+            TokenAmount {
+                token: mock_staking_derivative().address,
+                amount: Uint128(400),
+            }
+        );
+    }
+
+    #[test]
+    fn test_multi_input_route() {
+        let (_init_result, mut deps) = init_helper();
+        let input = |token: SecretContract, pair: SecretContract, amount: u128| {
+            let mut hops: VecDeque<Hop> = VecDeque::new();
+            hops.push_back(swap_hop(Token::Snip20(token), Some(pair)));
+            hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+            RouteInput {
+                amount: Uint128(amount),
+                hops,
+            }
+        };
+        let sscrt_input: RouteInput = input(mock_sscrt(), mock_pair_contract(), 1_000);
+        let token_input: RouteInput = input(mock_token(), mock_pair_contract_two(), 500);
+        let multi_input_route: MultiInputRoute = MultiInputRoute {
+            inputs: vec![sscrt_input.clone(), token_input.clone()],
+            estimated_amount: Uint128(2_100),
+            minimum_acceptable_amount: Uint128(2_000),
+            to: mock_user_address(),
+            allow_third_party_recipient: None,
+            private: None,
+        };
+        let collect_input_msg = |amount: u128| HandleMsg::Receive {
             from: mock_user_address(),
-            msg: None,
-            amount: transaction_amount,
+            msg: Some(to_binary(&ReceiveMsg::CollectInput {}).unwrap()),
+            amount: Uint128(amount),
         };
-        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
-        // == * it raises an error
+
+        // when the route has less than 2 inputs
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::CreateMultiInputRoute {
+                route: MultiInputRoute {
+                    inputs: vec![sscrt_input.clone()],
+                    ..multi_input_route.clone()
+                },
+            },
+        );
+        // * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
-            StdError::Unauthorized { backtrace: None }
+            StdError::generic_err("Multi-input route must have at least 2 inputs.")
         );
-        // == when from receiving hop pair contract
-        handle_msg = HandleMsg::Receive {
-            from: mock_pair_contract().address,
-            msg: None,
-            amount: transaction_amount,
+
+        // when the inputs end with different tokens
+        let mut other_output_input: RouteInput = token_input.clone();
+        other_output_input.hops[1] = swap_hop(mock_token_snip20(), None);
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::CreateMultiInputRoute {
+                route: MultiInputRoute {
+                    inputs: vec![sscrt_input.clone(), other_output_input],
+                    ..multi_input_route.clone()
+                },
+            },
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Inputs must end with the same token.")
+        );
+
+        // when the route is valid
+        let create_msg = HandleMsg::CreateMultiInputRoute {
+            route: multi_input_route.clone(),
         };
-        // === when not expected token
-        handle_result = handle(
+        handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            create_msg.clone(),
+        )
+        .unwrap();
+        // * it waits for the inputs
+        handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        let query_result = query(
+            &deps,
+            QueryMsg::MultiInputCollection {
+                address: mock_user_address(),
+                key: "key".to_string(),
+            },
+        )
+        .unwrap();
+        let query_answer: Option<MultiInputCollection> = from_binary(&query_result).unwrap();
+        assert_eq!(
+            query_answer,
+            Some(MultiInputCollection {
+                owner: mock_user_address(),
+                route: multi_input_route.clone(),
+                received: vec![false, false],
+            })
+        );
+        // = when another multi-input route is created
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            create_msg.clone(),
+        );
+        // = * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("A multi-input route is already being collected.")
+        );
+        // = when a token that isn't an input is received
+        let handle_result = handle(
             &mut deps,
             mock_env(mock_button().address, &[]),
-            handle_msg.clone(),
+            collect_input_msg(1_000),
         );
-        // === * it raises an error
+        // = * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
-            StdError::generic_err("Received crypto type or amount is wrong.")
+            StdError::generic_err("Received input isn't part of the multi-input route.")
         );
-        // === when expected token
-        // ==== when there are more hops after the next hop
-        // ==== * it sends the messages from hop_messages
-        let next_hop: Hop = hops.pop_front().unwrap();
-        let env = mock_env(mock_sscrt().address, &[]);
-        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
-        let mut handle_result_unwrapped = handle_result.unwrap();
+        // = when an input is received
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            collect_input_msg(1_000),
+        )
+        .unwrap();
+        // = * it logs how many inputs are remaining
+        assert_eq!(handle_result.log, vec![log("inputs_remaining", "1")]);
+        // == when the route is cancelled
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::CancelMultiInputRoute {},
+        )
+        .unwrap();
+        // == * it refunds the received inputs
         assert_eq!(
-            handle_result_unwrapped.messages,
-            hop_messages(next_hop.clone(), transaction_amount, &env).unwrap()
+            handle_result.messages,
+            vec![snip20::transfer_msg(
+                mock_user_address(),
+                Uint128(1_000),
+                None,
+                BLOCK_SIZE,
+                mock_sscrt().contract_hash,
+                mock_sscrt().address,
+            )
+            .unwrap()]
         );
-        // ==== * it stores the updated route state
-        let route_state = read_route_state(&deps.storage).unwrap().unwrap();
-        assert_eq!(route_state.current_hop, next_hop);
         assert_eq!(
-            route_state.remaining_route,
-            Route {
-                hops: hops.clone(),
-                estimated_amount,
-                minimum_acceptable_amount,
-                to: mock_user_address(),
+            read_multi_input_collection(&deps.storage, &mock_user_address()).unwrap(),
+            None
+        );
+
+        // when every input is received
+        handle(&mut deps, mock_env(mock_user_address(), &[]), create_msg).unwrap();
+        handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            collect_input_msg(1_000),
+        )
+        .unwrap();
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_token().address, &[]),
+            collect_input_msg(500),
+        )
+        .unwrap();
+        // * it swaps the first input
+        let mut first_hops: VecDeque<Hop> = sscrt_input.hops.clone();
+        let first_hop: Hop = first_hops.pop_front().unwrap();
+        assert_eq!(
+            handle_result.messages[0],
+            hop_messages(
+                first_hop.clone(),
+                Uint128(1_000),
+                &mock_env(mock_token().address, &[])
+            )
+            .unwrap()[0]
+        );
+        // * it keeps the other inputs to swap afterwards
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, first_hop);
+        assert_eq!(route_state.remaining_route.hops, first_hops);
+        assert_eq!(
+            route_state.remaining_inputs,
+            vec![token_input.clone()]
+                .into_iter()
+                .collect::<VecDeque<RouteInput>>()
+        );
+        assert_eq!(
+            read_multi_input_collection(&deps.storage, &mock_user_address()).unwrap(),
+            None
+        );
+        // = when the first input's output is received
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract().address,
+                msg: None,
+                amount: Uint128(1_200),
             },
+        )
+        .unwrap();
+        // = * it swaps the next input
+        let mut next_hops: VecDeque<Hop> = token_input.hops.clone();
+        let next_hop: Hop = next_hops.pop_front().unwrap();
+        assert_eq!(
+            handle_result.messages,
+            hop_messages(
+                next_hop.clone(),
+                Uint128(500),
+                &mock_env(mock_button().address, &[])
+            )
+            .unwrap()
         );
-        // ==== when this is the last hop
-        // ===== when the amount is less than the minimum_acceptable_amount
-        handle_msg = HandleMsg::Receive {
-            from: mock_pair_contract_two().address,
-            msg: None,
-            amount: (minimum_acceptable_amount - Uint128(1)).unwrap(),
-        };
-        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
-        // ===== * it raises an error
+        // = * it keeps the output
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, next_hop);
+        assert_eq!(route_state.remaining_route.hops, next_hops);
+        assert_eq!(route_state.collected_amount, Some(Uint128(1_200)));
+        // == when the summed outputs are less than the minimum_acceptable_amount
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract_two().address,
+                msg: None,
+                amount: Uint128(799),
+            },
+        );
+        // == * it raises an error
         assert_eq!(
             handle_result.unwrap_err(),
             StdError::generic_err("Operation fell short of minimum_acceptable_amount")
         );
-        // ===== when last token is a snip20
-        // ====== when the amount is equal to or less than the estimated amount
-        // ====== * it transfers the received amount to the user
-        handle_msg = HandleMsg::Receive {
-            from: mock_pair_contract_two().address,
-            msg: None,
-            amount: (estimated_amount - Uint128(1)).unwrap(),
-        };
-        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
-        handle_result_unwrapped = handle_result.unwrap();
+        // == when the summed outputs are at least the minimum_acceptable_amount
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract_two().address,
+                msg: None,
+                amount: Uint128(900),
+            },
+        )
+        .unwrap();
+        // == * it sends the summed outputs to the user
         assert_eq!(
-            handle_result_unwrapped.messages,
+            handle_result.messages,
             vec![snip20::send_msg(
-                route_state.remaining_route.to,
-                (estimated_amount - Uint128(1)).unwrap(),
+                mock_user_address(),
+                Uint128(2_100),
                 None,
                 None,
                 BLOCK_SIZE,
-                mock_sscrt().contract_hash,
-                mock_sscrt().address,
+                mock_button().contract_hash,
+                mock_button().address,
             )
             .unwrap()]
         );
-        // ====== * it logs the return amount
-        assert_eq!(
-            handle_result_unwrapped.log,
-            vec![log(
-                "return_amount",
-                (estimated_amount - Uint128(1)).unwrap().to_string()
-            )]
-        );
+    }
 
-        // ===== when the amount is greater than the estimated amount
-        // ===== * it sends any excess to the admin
-        // ===== * it transfers the received amount - the excess amount to the user
-        store_route_state(
-            &mut deps.storage,
-            &RouteState {
-                current_hop: Hop {
-                    from_token: mock_token_snip20(),
-                    redeem_denom: None,
-                    smart_contract: Some(mock_pair_contract_two()),
-                    migrate_to_token: None,
-                    shade_protocol_router_path: None,
-                },
-                remaining_route: Route {
-                    hops: hops.clone(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
-                    to: mock_user_address(),
-                },
+    #[test]
+    fn test_padded_responses() {
+        let mut deps = mock_dependencies(20, &[]);
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: None,
+                wrapped_tokens: None,
             },
         )
         .unwrap();
-        handle_msg = HandleMsg::Receive {
-            from: mock_pair_contract_two().address,
-            msg: None,
-            amount: estimated_amount + estimated_amount,
-        };
-        handle_result = handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg);
-        handle_result_unwrapped = handle_result.unwrap();
-        assert_eq!(
-            handle_result_unwrapped.messages,
-            vec![
-                snip20::transfer_msg(
-                    mock_contract_initiator_address(),
-                    estimated_amount,
-                    None,
-                    BLOCK_SIZE,
-                    mock_sscrt().contract_hash,
-                    mock_sscrt().address,
-                )
-                .unwrap(),
-                snip20::send_msg(
-                    mock_user_address(),
-                    estimated_amount,
-                    None,
-                    None,
-                    BLOCK_SIZE,
-                    mock_sscrt().contract_hash,
-                    mock_sscrt().address,
-                )
-                .unwrap(),
-            ]
-        );
-        // ====== * it logs the return amount
+
+        // when padding isn't set at init
+        // * it pads the data to the block size
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+        let data: Binary = handle_result.data.unwrap();
+        assert_eq!(data.len(), BLOCK_SIZE);
         assert_eq!(
-            handle_result_unwrapped.log,
-            vec![log("return_amount", estimated_amount.to_string())]
+            from_binary::<HandleAnswer>(&data).unwrap(),
+            HandleAnswer::SetViewingKey {
+                status: ResponseStatus::Success
+            }
         );
-        // ===== when last token is a native token
-        hops = VecDeque::new();
-        hops.push_back(Hop {
-            from_token: mock_token_native(),
-            redeem_denom: None,
-            smart_contract: None,
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
-        store_route_state(
-            &mut deps.storage,
-            &RouteState {
-                current_hop: Hop {
-                    from_token: mock_token_snip20(),
-                    redeem_denom: Some(mock_denom()),
-                    smart_contract: None,
-                    migrate_to_token: None,
-                    shade_protocol_router_path: None,
-                },
-                remaining_route: Route {
-                    hops: hops.clone(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
-                    to: mock_user_address(),
-                },
+        // * it pads the log values to the block size
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&ReceiveMsg::PlaceLimitOrder {
+                        route_template: Route {
+                            hops,
+                            estimated_amount: Uint128(2_000_000),
+                            minimum_acceptable_amount: Uint128(1_800_000),
+                            to: mock_user_address(),
+                            allow_third_party_recipient: None,
+                            zap_in: None,
+                            min_profit: None,
+                            private: None,
+                        },
+                        min_output: Uint128(1_900_000),
+                        expiration: None,
+                    })
+                    .unwrap(),
+                ),
+                amount: Uint128(1_000_000),
             },
         )
         .unwrap();
-        // ====== when the amount is equal to or less than the estimated amount
-        // ====== * it transfers the received amount to the user
-        handle_msg = HandleMsg::Receive {
-            from: env.contract.address.clone(),
-            msg: None,
-            amount: estimated_amount,
+        assert_eq!(handle_result.log[0].key, "order_id");
+        assert_eq!(handle_result.log[0].value.len(), BLOCK_SIZE);
+        assert_eq!(handle_result.log[0].value.trim_end(), "0");
+    }
+
+    #[test]
+    fn test_pair_oracle() {
+        let mut deps = mock_dependencies_with_wasm_querier();
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: Some(false),
+                wrapped_tokens: None,
+            },
+        )
+        .unwrap();
+        let pair_oracle: PairOracle = PairOracle {
+            oracle: mock_oracle(),
+            base_symbol: "SCRT".to_string(),
+            quote_symbol: "USD".to_string(),
+            input_decimals: 6,
+            output_decimals: 6,
+            max_deviation_basis_points: 100,
         };
-        handle_result = handle(
+        let handle_msg = HandleMsg::SetPairOracle {
+            input_token: mock_sscrt().address,
+            output_token: mock_button().address,
+            pair_oracle: Some(pair_oracle.clone()),
+        };
+
+        // when called by a non-admin
+        let handle_result = handle(
             &mut deps,
-            mock_env(
-                env.contract.address.clone(),
-                &[Coin {
-                    amount: estimated_amount,
-                    denom: mock_denom(),
-                }],
-            ),
-            handle_msg,
+            mock_env(mock_user_address(), &[]),
+            handle_msg.clone(),
         );
-        handle_result_unwrapped = handle_result.unwrap();
+        // * it raises an Unauthorized error
         assert_eq!(
-            handle_result_unwrapped.messages,
-            vec![CosmosMsg::Bank(BankMsg::Send {
-                from_address: env.contract.address.clone(),
-                to_address: mock_user_address(),
-                amount: vec![Coin {
-                    amount: estimated_amount,
-                    denom: mock_denom(),
-                }],
-            })]
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when called by the admin
+        // = when the decimals are more than 18
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetPairOracle {
+                input_token: mock_sscrt().address,
+                output_token: mock_button().address,
+                pair_oracle: Some(PairOracle {
+                    output_decimals: 39,
+                    ..pair_oracle.clone()
+                }),
+            },
         );
+        // = * it raises an error
         assert_eq!(
-            handle_result_unwrapped.log,
-            vec![log("return_amount", estimated_amount.to_string())]
+            handle_result.unwrap_err(),
+            StdError::generic_err("Pair oracle decimals can't be more than 18.")
         );
-        // ===== when the amount is greater than the estimated amount
-        // ===== * it sends any excess to the admin
-        // ===== * it transfers the received amount - the excess amount to the user
-        hops = VecDeque::new();
-        hops.push_back(Hop {
-            from_token: mock_token_native(),
-            redeem_denom: None,
-            smart_contract: None,
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        });
-        store_route_state(
-            &mut deps.storage,
-            &RouteState {
-                current_hop: Hop {
-                    from_token: mock_token_snip20(),
-                    redeem_denom: Some(mock_denom()),
-                    smart_contract: None,
-                    migrate_to_token: None,
-                    shade_protocol_router_path: None,
-                },
-                remaining_route: Route {
-                    hops: hops.clone(),
-                    estimated_amount: estimated_amount,
-                    minimum_acceptable_amount: minimum_acceptable_amount,
-                    to: mock_user_address(),
-                },
+        // = when the decimals are valid
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            handle_msg,
+        )
+        .unwrap();
+        // = * it stores the oracle for the input and output tokens
+        let query_result = query(
+            &deps,
+            QueryMsg::PairOracle {
+                input_token: mock_sscrt().address,
+                output_token: mock_button().address,
             },
         )
         .unwrap();
-        handle_msg = HandleMsg::Receive {
-            from: env.contract.address.clone(),
+        let query_answer: Option<PairOracle> = from_binary(&query_result).unwrap();
+        assert_eq!(query_answer, Some(pair_oracle));
+
+        // when a route from the input token to the output token is sent in
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops,
+                    estimated_amount: Uint128(2_000_000),
+                    minimum_acceptable_amount: Uint128(1),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                })
+                .unwrap(),
+            ),
+            amount: Uint128(1_000_000),
+        };
+        handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        // * it stores the oracle price less the max deviation as the oracle minimum
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.oracle_minimum_amount, Some(Uint128(1_980_000)));
+
+        // = when the output is worse than the oracle minimum
+        let handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
             msg: None,
-            amount: estimated_amount + estimated_amount,
+            amount: Uint128(1_979_999),
         };
-        handle_result = handle(
-            &mut deps,
-            mock_env(
-                env.contract.address.clone(),
-                &[Coin {
-                    amount: estimated_amount + estimated_amount,
-                    denom: mock_denom(),
-                }],
-            ),
-            handle_msg,
-        );
-        handle_result_unwrapped = handle_result.unwrap();
+        let handle_result = handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg);
+        // = * it raises an error
         assert_eq!(
-            handle_result_unwrapped.messages,
-            vec![
-                CosmosMsg::Bank(BankMsg::Send {
-                    from_address: env.contract.address.clone(),
-                    to_address: mock_contract_initiator_address(),
-                    amount: vec![Coin {
-                        amount: estimated_amount,
-                        denom: mock_denom(),
-                    }],
-                }),
-                CosmosMsg::Bank(BankMsg::Send {
-                    from_address: env.contract.address.clone(),
-                    to_address: mock_user_address(),
-                    amount: vec![Coin {
-                        amount: estimated_amount,
-                        denom: mock_denom(),
-                    }],
-                })
-            ]
+            handle_result.unwrap_err(),
+            StdError::generic_err("Output is worse than the oracle price.")
+        );
+
+        // = when the output is at least the oracle minimum
+        let handle_msg = HandleMsg::Receive {
+            from: mock_pair_contract().address,
+            msg: None,
+            amount: Uint128(1_980_000),
+        };
+        let handle_result = handle(&mut deps, mock_env(mock_button().address, &[]), handle_msg);
+        // = * it sends the output to the user
+        assert!(handle_result.is_ok());
+
+        // when the oracle is removed
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetPairOracle {
+                input_token: mock_sscrt().address,
+                output_token: mock_button().address,
+                pair_oracle: None,
+            },
+        )
+        .unwrap();
+        // * it no longer has an oracle for the input and output tokens
+        assert_eq!(
+            read_pair_oracle(&deps.storage, &mock_sscrt().address, &mock_button().address).unwrap(),
+            None
         );
+
+        // when the input token has more decimals than the output token
+        store_pair_oracle(
+            &mut deps.storage,
+            &mock_sscrt().address,
+            &mock_button().address,
+            Some(PairOracle {
+                oracle: mock_oracle(),
+                base_symbol: "SCRT".to_string(),
+                quote_symbol: "USD".to_string(),
+                input_decimals: 18,
+                output_decimals: 6,
+                max_deviation_basis_points: 0,
+            }),
+        )
+        .unwrap();
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let expected_amount: Option<Uint128> =
+            oracle_minimum_amount(&deps, &hops, Uint128(1_000_900_000_000_000)).unwrap();
+        // * it applies the rate before truncating to the output's decimals
+        assert_eq!(expected_amount, Some(Uint128(2_001)));
+        // = when the amount is a thousand 18 decimal tokens
+        let expected_amount: Option<Uint128> =
+            oracle_minimum_amount(&deps, &hops, Uint128(1_000 * 10u128.pow(18))).unwrap();
+        // = * it applies the rate without overflowing
+        assert_eq!(expected_amount, Some(Uint128(2_000_000_000)));
+
+        // when the output token has more decimals than the input token
+        store_pair_oracle(
+            &mut deps.storage,
+            &mock_sscrt().address,
+            &mock_button().address,
+            Some(PairOracle {
+                oracle: mock_oracle(),
+                base_symbol: "SCRT".to_string(),
+                quote_symbol: "USD".to_string(),
+                input_decimals: 6,
+                output_decimals: 18,
+                max_deviation_basis_points: 100,
+            }),
+        )
+        .unwrap();
+        let expected_amount: Option<Uint128> =
+            oracle_minimum_amount(&deps, &hops, Uint128(10u128.pow(21))).unwrap();
+        // * it scales the amount up to the output's decimals without overflowing
         assert_eq!(
-            handle_result_unwrapped.log,
-            vec![log("return_amount", estimated_amount.to_string())]
+            expected_amount,
+            Some(Uint128(1_980_000_000 * 10u128.pow(24)))
         );
     }
 
     #[test]
-    fn test_hop_messages() {
-        let env = mock_env(mock_user_address(), &[]);
-        let amount = Uint128(555);
-        let shade_protocol_router_path: Option<Vec<SecretContractForShadeProtocol>> =
-            Some(vec![SecretContractForShadeProtocol {
-                addr: mock_pair_contract().address.to_string(),
-                code_hash: mock_pair_contract().contract_hash,
-            }]);
-        let mut hop: Hop = Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: None,
-            smart_contract: Some(mock_shade_protocol_router()),
-            migrate_to_token: None,
-            shade_protocol_router_path,
-        };
-        // when hop.from_token == Token::Snip20
-        // = when shade_protocol_router_path is present
-        // = * it sends the snip 20 to the hop smart contract with the SwapTokensForExact struct and path
-        let mut messages: Vec<CosmosMsg> = hop_messages(hop.clone(), amount, &env).unwrap();
-        assert_eq!(
-            messages,
-            vec![snip20::send_msg(
-                hop.smart_contract.unwrap().address,
-                amount,
-                // build swap msg for the next hop
-                Some(
-                    to_binary(&ShadeProtocol::SwapTokensForExact {
-                        // set the recepient of the swap to be this contract (the router)
-                        path: hop.shade_protocol_router_path.unwrap(),
+    fn test_private_routes() {
+        let (_init_result, mut deps) = init_helper();
+        handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            HandleMsg::SetViewingKey {
+                key: "key".to_string(),
+                padding: None,
+            },
+        )
+        .unwrap();
+
+        // when a private route starts
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&Route {
+                        hops,
+                        estimated_amount: Uint128(2_000),
+                        minimum_acceptable_amount: Uint128(1_500),
+                        to: mock_user_address(),
+                        allow_third_party_recipient: None,
+                        zap_in: None,
+                        min_profit: None,
+                        private: Some(true),
                     })
-                    .unwrap()
+                    .unwrap(),
                 ),
-                None,
-                BLOCK_SIZE,
-                mock_sscrt().contract_hash,
-                mock_sscrt().address,
-            )
-            .unwrap()]
-        );
-        // = when migrate_to_token is present
-        hop = Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: None,
-            smart_contract: Some(mock_shade_protocol_router()),
-            migrate_to_token: Some(mock_button()),
-            shade_protocol_router_path: None,
-        };
-        // = * it sends the snip 20 to the hop smart contract and then it sends the migrate_to_token to itself
-        messages = hop_messages(hop.clone(), amount, &env).unwrap();
+                amount: Uint128(1_000),
+            },
+        )
+        .unwrap();
+        // * it doesn't log the amounts
         assert_eq!(
-            messages,
+            handle_result.log,
             vec![
-                snip20::send_msg(
-                    hop.smart_contract.unwrap().address,
-                    amount,
-                    // build swap msg for the next hop
-                    None,
-                    None,
-                    BLOCK_SIZE,
-                    mock_sscrt().contract_hash,
-                    mock_sscrt().address,
-                )
-                .unwrap(),
-                snip20::send_msg(
-                    env.contract.address.clone(),
-                    amount,
-                    // build swap msg for the next hop
-                    None,
-                    None,
-                    BLOCK_SIZE,
-                    mock_button().contract_hash,
-                    mock_button().address,
-                )
-                .unwrap(),
+                log("route_input_token", mock_sscrt().address),
+                log("hop_index", "0"),
+                log("hop_kind", "secret_swap"),
+                log("hop_token_in", mock_sscrt().address),
+                log("hop_token_out", mock_button().address),
             ]
         );
-        // = when redeem_denom is present
-        // = * it unwraps the token and then sends the native token to itself with a receive message
-        hop = Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: Some(mock_denom()),
-            smart_contract: None,
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        };
-        messages = hop_messages(hop, amount, &env).unwrap();
+
+        // = when the route pays out
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_button().address, &[]),
+            HandleMsg::Receive {
+                from: mock_pair_contract().address,
+                msg: None,
+                amount: Uint128(2_100),
+            },
+        )
+        .unwrap();
+        // = * it doesn't log the amounts
         assert_eq!(
-            messages,
+            handle_result.log,
             vec![
-                snip20::redeem_msg(
-                    amount,
-                    Some(mock_denom()),
-                    None,
-                    BLOCK_SIZE,
-                    mock_sscrt().contract_hash,
-                    mock_sscrt().address,
-                )
-                .unwrap(),
-                CosmosMsg::Wasm(WasmMsg::Execute {
-                    contract_addr: env.contract.address.clone(),
-                    callback_code_hash: env.contract_code_hash.clone(),
-                    msg: to_binary(&HandleMsg::Receive {
-                        from: env.contract.address.clone(),
-                        msg: None,
-                        amount,
-                    })
-                    .unwrap(),
-                    send: vec![Coin {
-                        amount,
-                        denom: mock_denom()
-                    }],
-                }),
+                log("fee_token", mock_button().address),
+                log("payout_recipient", mock_user_address()),
+                log("payout_token", mock_button().address),
             ]
         );
-        // = when only smart_contract is present
-        // = It sends a swap request to specificed smart contract
-        hop = Hop {
-            from_token: mock_token_snip20(),
-            redeem_denom: None,
-            smart_contract: Some(mock_pair_contract()),
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        };
-        messages = hop_messages(hop, amount, &env).unwrap();
-        assert_eq!(
-            messages,
-            vec![snip20::send_msg(
-                mock_pair_contract().address,
-                amount,
-                Some(
-                    to_binary(&Snip20Swap::Swap {
-                        // set expected_return to None because we don't care about slippage mid-route
-                        expected_return: None,
-                        // set the recepient of the swap to be this contract (the router)
-                        to: Some(env.contract.address.clone()),
-                    })
-                    .unwrap()
-                ),
-                None,
-                BLOCK_SIZE,
-                mock_sscrt().contract_hash,
-                mock_sscrt().address,
-            )
-            .unwrap(),]
-        );
-        // when hop.from_token == Token::Native
-        // = * it wraps the contract then sends it to itself
-        hop = Hop {
-            from_token: mock_token_native(),
-            redeem_denom: Some(mock_denom()),
-            smart_contract: None,
-            migrate_to_token: None,
-            shade_protocol_router_path: None,
-        };
-        messages = hop_messages(hop, amount, &env).unwrap();
+        // = * it returns the amounts in data
         assert_eq!(
-            messages,
-            vec![
-                Snip20::Deposit { padding: None }
-                    .to_cosmos_msg(
-                        BLOCK_SIZE,
-                        mock_sscrt().contract_hash,
-                        mock_sscrt().address,
-                        Some(Coin {
-                            amount,
-                            denom: mock_denom(),
-                        }),
-                    )
-                    .unwrap(),
-                snip20::send_msg(
-                    env.contract.address.clone(),
-                    amount,
-                    None,
-                    None,
-                    BLOCK_SIZE,
-                    mock_sscrt().contract_hash,
-                    mock_sscrt().address,
-                )
-                .unwrap()
-            ]
+            from_binary::<SwapResult>(&handle_result.data.unwrap()).unwrap(),
+            SwapResult {
+                return_amount: Uint128(2_000),
+                fee_amount: Uint128(100),
+                output_token: mock_button().address,
+                hops_executed: 1,
+            }
         );
+        // = * it records the swap in the user's history
+        let query_result = query(
+            &deps,
+            QueryMsg::SwapHistory {
+                address: mock_user_address(),
+                key: "key".to_string(),
+                page: 0,
+                page_size: 10,
+            },
+        )
+        .unwrap();
+        let swaps: Vec<Swap> = from_binary(&query_result).unwrap();
+        assert_eq!(swaps.len(), 1);
     }
 
     #[test]
     fn test_register_tokens() {
-        let (_init_result, mut deps) = init_helper();
+        let mut deps = mock_dependencies_with_wasm_querier();
         let env = mock_env(mock_user_address(), &[]);
+        let admin_env = mock_env(mock_contract_initiator_address(), &[]);
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: Some(false),
+                wrapped_tokens: None,
+            },
+        )
+        .unwrap();
+        let button_metadata: TokenMetadata = TokenMetadata {
+            token: mock_button(),
+            name: "Button".to_string(),
+            symbol: "BUTT".to_string(),
+            decimals: 6,
+        };
+        let token_metadata: TokenMetadata = TokenMetadata {
+            token: mock_token(),
+            name: "Token".to_string(),
+            symbol: "TKN".to_string(),
+            decimals: 18,
+        };
+
+        // when refreshing tokens as a non-admin
+        let handle_result = handle(
+            &mut deps,
+            env.clone(),
+            HandleMsg::RefreshTokens {
+                tokens: vec![mock_button().address],
+            },
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when refreshing a token that isn't registered
+        let handle_result = handle(
+            &mut deps,
+            admin_env.clone(),
+            HandleMsg::RefreshTokens {
+                tokens: vec![mock_button().address],
+            },
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Token isn't registered.")
+        );
 
-        // When tokens are in the parameter
+        // When a non-admin registers tokens
         let handle_msg = HandleMsg::RegisterTokens {
             tokens: vec![mock_button(), mock_token()],
         };
         let handle_result = handle(&mut deps, env.clone(), handle_msg);
         let handle_result_unwrapped = handle_result.unwrap();
         // * it sends a message to register receive for the token and sets a viewing key
+        let contract_viewing_key: String = read_contract_viewing_key(&deps.storage).unwrap();
         assert_eq!(
             handle_result_unwrapped.messages,
             vec![
@@ -1353,6 +6989,14 @@ mod tests {
                     mock_button().address,
                 )
                 .unwrap(),
+                snip20::set_viewing_key_msg(
+                    contract_viewing_key.clone(),
+                    None,
+                    BLOCK_SIZE,
+                    mock_button().contract_hash,
+                    mock_button().address,
+                )
+                .unwrap(),
                 snip20::register_receive_msg(
                     mock_contract().contract_hash,
                     None,
@@ -1361,8 +7005,80 @@ mod tests {
                     mock_token().address,
                 )
                 .unwrap(),
+                snip20::set_viewing_key_msg(
+                    contract_viewing_key,
+                    None,
+                    BLOCK_SIZE,
+                    mock_token().contract_hash,
+                    mock_token().address,
+                )
+                .unwrap(),
             ]
         );
+        // * it doesn't cache the tokens' metadata
+        let query_result = query(
+            &deps,
+            QueryMsg::Tokens {
+                page: 0,
+                page_size: 10,
+            },
+        )
+        .unwrap();
+        let tokens: Vec<TokenMetadata> = from_binary(&query_result).unwrap();
+        assert_eq!(tokens, vec![]);
+
+        // When the admin registers tokens
+        // = when one of them can't answer token_info
+        let handle_result = handle(
+            &mut deps,
+            admin_env.clone(),
+            HandleMsg::RegisterTokens {
+                tokens: vec![mock_button(), mock_sscrt(), mock_token()],
+            },
+        );
+        // == it registers all of them
+        assert_eq!(handle_result.unwrap().messages.len(), 6);
+        // == it caches the metadata of the others
+        let query_result = query(
+            &deps,
+            QueryMsg::Tokens {
+                page: 0,
+                page_size: 10,
+            },
+        )
+        .unwrap();
+        let tokens: Vec<TokenMetadata> = from_binary(&query_result).unwrap();
+        assert_eq!(tokens, vec![button_metadata.clone(), token_metadata]);
+
+        // when a registered token is refreshed
+        store_token_metadata(
+            &mut deps.storage,
+            &TokenMetadata {
+                symbol: "OLD".to_string(),
+                ..button_metadata.clone()
+            },
+        )
+        .unwrap();
+        handle(
+            &mut deps,
+            admin_env,
+            HandleMsg::RefreshTokens {
+                tokens: vec![mock_button().address],
+            },
+        )
+        .unwrap();
+        // * it fetches its metadata again without registering it twice
+        let query_result = query(
+            &deps,
+            QueryMsg::Tokens {
+                page: 0,
+                page_size: 1,
+            },
+        )
+        .unwrap();
+        let tokens: Vec<TokenMetadata> = from_binary(&query_result).unwrap();
+        assert_eq!(tokens, vec![button_metadata]);
+        assert_eq!(read_token_addresses(&deps.storage, 0, 10).unwrap().len(), 2);
     }
 
     #[test]
@@ -1431,5 +7147,505 @@ mod tests {
             )
             .unwrap()]
         );
+
+        // == when part of the balance is escrowed
+        deps.querier = MockQuerier::new(&[(
+            &HumanAddr::from(MOCK_CONTRACT_ADDR),
+            &[Coin {
+                denom: mock_denom(),
+                amount: Uint128(12),
+            }],
+        )]);
+        add_escrowed_amount(&mut deps.storage, &mock_denom(), Uint128(10)).unwrap();
+        // === when the amount is more than the balance that isn't escrowed
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::RescueTokens {
+                amount,
+                denom: Some(mock_denom()),
+                token: None,
+            },
+        );
+        // === * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Amount is more than the balance that isn't escrowed.")
+        );
+        // === when the amount isn't escrowed
+        // === * it sends the amount to the admin
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::RescueTokens {
+                amount: Uint128(2),
+                denom: Some(mock_denom()),
+                token: None,
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_set_keepers() {
+        let (_init_result, mut deps) = init_helper();
+        let handle_msg = HandleMsg::SetKeepers {
+            keepers: vec![mock_user_address()],
+        };
+        // = when called by a non-admin
+        // = * it raises an Unauthorized error
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            handle_msg.clone(),
+        );
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // = when called by the admin
+        // = * it replaces the keepers
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            handle_msg,
+        )
+        .unwrap();
+        let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
+        assert_eq!(config.keepers, vec![mock_user_address()]);
+    }
+
+    #[test]
+    fn test_swap_many() {
+        let (_init_result, mut deps) = init_helper();
+        let route = |first_hop: Hop, last_token: Token, to: HumanAddr| {
+            let mut hops: VecDeque<Hop> = VecDeque::new();
+            hops.push_back(first_hop);
+            hops.push_back(swap_hop(last_token, None));
+            Route {
+                hops,
+                estimated_amount: Uint128(2_000),
+                minimum_acceptable_amount: Uint128(1_900),
+                to,
+                allow_third_party_recipient: None,
+                zap_in: None,
+                min_profit: None,
+                private: None,
+            }
+        };
+        let snip20_route: Route = route(
+            swap_hop(mock_token_snip20(), Some(mock_pair_contract())),
+            Token::Snip20(mock_button()),
+            mock_user_address(),
+        );
+        let native_route: Route = route(
+            Hop {
+                redeem_denom: Some(mock_denom()),
+                ..swap_hop(mock_token_native(), None)
+            },
+            mock_token_snip20(),
+            mock_user_address(),
+        );
+        let routes: Vec<SwapManyRoute> = vec![
+            SwapManyRoute {
+                amount: Uint128(1_000),
+                route: snip20_route.clone(),
+            },
+            SwapManyRoute {
+                amount: Uint128(3_000),
+                route: native_route.clone(),
+            },
+        ];
+        let native_funds: Vec<Coin> = vec![Coin {
+            amount: Uint128(3_000),
+            denom: mock_denom(),
+        }];
+
+        // when there are no routes
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &native_funds),
+            HandleMsg::SwapMany { routes: vec![] },
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("SwapMany must have at least 1 route.")
+        );
+
+        // when a route is to someone else without allow_third_party_recipient
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &native_funds),
+            HandleMsg::SwapMany {
+                routes: vec![SwapManyRoute {
+                    amount: Uint128(1_000),
+                    route: route(
+                        swap_hop(mock_token_snip20(), Some(mock_pair_contract())),
+                        Token::Snip20(mock_button()),
+                        mock_merchant_address(),
+                    ),
+                }],
+            },
+        );
+        // * it raises an Unauthorized error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when the sent funds don't match the native routes
+        let handle_result = handle(
+            &mut deps,
+            mock_env(
+                mock_user_address(),
+                &[Coin {
+                    amount: Uint128(2_999),
+                    denom: mock_denom(),
+                }],
+            ),
+            HandleMsg::SwapMany {
+                routes: routes.clone(),
+            },
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Sent funds must match the native routes' amounts.")
+        );
+
+        // when the routes are valid and funded
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &native_funds),
+            HandleMsg::SwapMany { routes },
+        )
+        .unwrap();
+        // * it transfers the SNIP-20 inputs from the user and starts each route in order
+        let start_route_msg = |amount: u128, route: Route| {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: mock_contract().address,
+                callback_code_hash: mock_contract().contract_hash,
+                msg: to_binary(&HandleMsg::StartRoute {
+                    amount: Uint128(amount),
+                    route,
+                    user: mock_user_address(),
+                })
+                .unwrap(),
+                send: vec![],
+            })
+        };
+        assert_eq!(
+            handle_result.messages,
+            vec![
+                snip20::transfer_from_msg(
+                    mock_user_address(),
+                    mock_contract().address,
+                    Uint128(1_000),
+                    None,
+                    BLOCK_SIZE,
+                    mock_sscrt().contract_hash,
+                    mock_sscrt().address,
+                )
+                .unwrap(),
+                start_route_msg(1_000, snip20_route.clone()),
+                start_route_msg(3_000, native_route),
+            ]
+        );
+        // * it logs a summary of the routes
+        assert_eq!(
+            handle_result.log,
+            vec![
+                log("route_count", "2"),
+                log(
+                    "route_0",
+                    format!(
+                        "1000 {} -> {} minimum 1900",
+                        mock_sscrt().address,
+                        mock_button().address
+                    )
+                ),
+                log(
+                    "route_1",
+                    format!(
+                        "3000 {} -> {} minimum 1900",
+                        mock_sscrt().address,
+                        mock_sscrt().address
+                    )
+                ),
+            ]
+        );
+
+        // when StartRoute is called by someone other than this contract
+        let handle_msg = HandleMsg::StartRoute {
+            amount: Uint128(1_000),
+            route: snip20_route.clone(),
+            user: mock_user_address(),
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_user_address(), &[]),
+            handle_msg.clone(),
+        );
+        // * it raises an Unauthorized error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::Unauthorized { backtrace: None }
+        );
+
+        // when StartRoute is called by this contract
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_contract().address, &[]),
+            handle_msg,
+        )
+        .unwrap();
+        // * it starts the route
+        let mut hops: VecDeque<Hop> = snip20_route.hops.clone();
+        let first_hop: Hop = hops.pop_front().unwrap();
+        assert_eq!(
+            handle_result.messages[0],
+            hop_messages(
+                first_hop.clone(),
+                Uint128(1_000),
+                &mock_env(mock_contract().address, &[])
+            )
+            .unwrap()[0]
+        );
+        let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+        assert_eq!(route_state.current_hop, first_hop);
+        assert_eq!(route_state.remaining_route.hops, hops);
+    }
+
+    #[test]
+    fn test_trigger_orders() {
+        let mut deps = mock_dependencies_with_wasm_querier();
+        init(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: None,
+                pad_responses: Some(false),
+                wrapped_tokens: None,
+            },
+        )
+        .unwrap();
+        let amount: Uint128 = Uint128(1_000_000);
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(swap_hop(mock_token_snip20(), Some(mock_pair_contract())));
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let route_template: Route = Route {
+            hops: hops.clone(),
+            estimated_amount: Uint128(2_000_000),
+            minimum_acceptable_amount: Uint128(1_800_000),
+            to: mock_user_address(),
+            allow_third_party_recipient: None,
+            zap_in: None,
+            min_profit: None,
+            private: None,
+        };
+        let trigger = |kind: TriggerKind, rate: u128| Trigger {
+            oracle: mock_oracle(),
+            base_symbol: "SCRT".to_string(),
+            quote_symbol: "USD".to_string(),
+            kind,
+            rate: Uint128(rate),
+        };
+        for trigger in [
+            trigger(TriggerKind::StopLoss, 1_900_000_000_000_000_000),
+            trigger(TriggerKind::TakeProfit, 2_100_000_000_000_000_000),
+            trigger(TriggerKind::StopLoss, 2_000_000_000_000_000_000),
+            trigger(TriggerKind::TakeProfit, 1_900_000_000_000_000_000),
+        ] {
+            let handle_msg = HandleMsg::Receive {
+                from: mock_user_address(),
+                msg: Some(
+                    to_binary(&ReceiveMsg::PlaceTriggerOrder {
+                        route_template: route_template.clone(),
+                        trigger,
+                        expiration: None,
+                    })
+                    .unwrap(),
+                ),
+                amount,
+            };
+            handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        }
+        // * it stores the orders with the route template's minimum_acceptable_amount as min_output
+        let order: LimitOrder = read_order(&deps.storage, 0).unwrap().unwrap();
+        assert_eq!(order.min_output, route_template.minimum_acceptable_amount);
+
+        // when the oracle's rate hasn't crossed the trigger's rate
+        let env = mock_env(mock_merchant_address(), &[]);
+        for id in 0..2 {
+            let handle_result = handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteOrder {
+                    id,
+                    route: route_template.clone(),
+                },
+            );
+            // * it raises an error
+            assert_eq!(
+                handle_result.unwrap_err(),
+                StdError::generic_err("Order hasn't been triggered.")
+            );
+        }
+
+        // when the oracle's rate has crossed the trigger's rate
+        for id in 2..4 {
+            handle(
+                &mut deps,
+                env.clone(),
+                HandleMsg::ExecuteOrder {
+                    id,
+                    route: route_template.clone(),
+                },
+            )
+            .unwrap();
+            // * it executes the order bounded by the route template's minimum_acceptable_amount
+            let route_state: RouteState = read_route_state(&deps.storage).unwrap().unwrap();
+            assert_eq!(
+                route_state.remaining_route.minimum_acceptable_amount,
+                route_template.minimum_acceptable_amount
+            );
+            assert_eq!(read_order(&deps.storage, id).unwrap(), None);
+            delete_route_state(&mut deps.storage);
+        }
+    }
+
+    #[test]
+    fn test_whitelisted_hops() {
+        let (_init_result, mut deps) = init_helper();
+        let migration: Migration = Migration {
+            from_token: mock_sscrt().address,
+            smart_contract: mock_shade_protocol_router().address,
+            migrate_to_token: mock_button().address,
+        };
+
+        // when the whitelists are set in init
+        let mut init_deps = mock_dependencies(20, &[]);
+        init(
+            &mut init_deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            InitMsg {
+                entropy: "entropy".to_string(),
+                migrations: Some(vec![migration.clone()]),
+                pad_responses: Some(false),
+                wrapped_tokens: Some(vec![mock_wrapped_token()]),
+            },
+        )
+        .unwrap();
+        // * it stores them in the config
+        let config: Config = TypedStore::attach(&init_deps.storage)
+            .load(CONFIG_KEY)
+            .unwrap();
+        assert_eq!(config.migrations, vec![migration.clone()]);
+        assert_eq!(config.wrapped_tokens, vec![mock_wrapped_token()]);
+
+        // when the whitelists are set by a non-admin
+        // * it raises an Unauthorized error
+        for handle_msg in [
+            HandleMsg::SetMigrations {
+                migrations: vec![migration.clone()],
+            },
+            HandleMsg::SetWrappedTokens {
+                wrapped_tokens: vec![mock_wrapped_token()],
+            },
+        ] {
+            assert_eq!(
+                handle(&mut deps, mock_env(mock_user_address(), &[]), handle_msg).unwrap_err(),
+                StdError::Unauthorized { backtrace: None }
+            );
+        }
+
+        // when a route migrates through a contract that isn't whitelisted
+        let mut hops: VecDeque<Hop> = VecDeque::new();
+        hops.push_back(Hop {
+            migrate_to_token: Some(mock_button()),
+            ..swap_hop(mock_token_snip20(), Some(mock_shade_protocol_router()))
+        });
+        hops.push_back(swap_hop(Token::Snip20(mock_button()), None));
+        let handle_msg = HandleMsg::Receive {
+            from: mock_user_address(),
+            msg: Some(
+                to_binary(&Route {
+                    hops,
+                    estimated_amount: Uint128(1_000_000),
+                    minimum_acceptable_amount: Uint128(1_000_000),
+                    to: mock_user_address(),
+                    allow_third_party_recipient: None,
+                    zap_in: None,
+                    min_profit: None,
+                    private: None,
+                })
+                .unwrap(),
+            ),
+            amount: Uint128(1_000_000),
+        };
+        let handle_result = handle(
+            &mut deps,
+            mock_env(mock_sscrt().address, &[]),
+            handle_msg.clone(),
+        );
+        // * it raises an error
+        assert_eq!(
+            handle_result.unwrap_err(),
+            StdError::generic_err("Migration isn't whitelisted.")
+        );
+
+        // when the admin whitelists the migration
+        handle(
+            &mut deps,
+            mock_env(mock_contract_initiator_address(), &[]),
+            HandleMsg::SetMigrations {
+                migrations: vec![migration.clone()],
+            },
+        )
+        .unwrap();
+        let config: Config = TypedStore::attach(&deps.storage).load(CONFIG_KEY).unwrap();
+        assert_eq!(config.migrations, vec![migration]);
+        // * the route can migrate through it
+        handle(&mut deps, mock_env(mock_sscrt().address, &[]), handle_msg).unwrap();
+        assert!(read_route_state(&deps.storage).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_wide_multiply_ratio() {
+        // when the product fits in u128
+        // * it truncates like multiply_ratio
+        assert_eq!(
+            wide_multiply_ratio(Uint128(10), 1, 3).unwrap(),
+            Uint128(10).multiply_ratio(1u128, 3u128)
+        );
+
+        // when the product of 18 decimal amounts is beyond u128
+        // * it returns the ratio
+        let amount: Uint128 = Uint128(1_000 * 10u128.pow(18));
+        assert_eq!(
+            wide_multiply_ratio(amount, 10u128.pow(25), 2 * 10u128.pow(25)).unwrap(),
+            Uint128(500 * 10u128.pow(18))
+        );
+        assert_eq!(
+            wide_multiply_ratio(Uint128(u128::MAX), u128::MAX, u128::MAX).unwrap(),
+            Uint128(u128::MAX)
+        );
+
+        // when the result is beyond u128
+        // * it raises an error
+        assert_eq!(
+            wide_multiply_ratio(Uint128(u128::MAX), 2, 1).unwrap_err(),
+            StdError::generic_err("Amount is too large.")
+        );
+
+        // when the denominator is zero
+        // * it raises an error
+        assert_eq!(
+            wide_multiply_ratio(amount, 1, 0).unwrap_err(),
+            StdError::generic_err("Can't divide by zero.")
+        );
     }
 }
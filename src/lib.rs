@@ -4,6 +4,7 @@ mod constants;
 pub mod contract;
 pub mod msg;
 pub mod state;
+mod viewing_key;
 
 #[cfg(target_arch = "wasm32")]
 mod wasm {
@@ -1,12 +1,40 @@
-use crate::state::{SecretContract, SecretContractForShadeProtocol};
-use cosmwasm_std::{to_binary, Binary, Coin, CosmosMsg, HumanAddr, StdResult, Uint128, WasmMsg};
+use crate::asset::Asset;
+use crate::constants::BLOCK_SIZE;
+use crate::state::{
+    DcaSchedule, Migration, MultiInputRoute, PairOracle, Protocol, Route, SecretContract,
+    SecretContractForShadeProtocol, SiennaSwapHop, Trigger, WrappedToken,
+};
+use crate::viewing_key::ViewingKey;
+use cosmwasm_std::{
+    to_binary, Binary, Coin, CosmosMsg, Decimal, HumanAddr, StdResult, Uint128, WasmMsg,
+};
 use schemars::JsonSchema;
-use secret_toolkit::utils::space_pad;
+use secret_toolkit::permit::Permit;
+use secret_toolkit::utils::{space_pad, HandleCallback, Query};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct InitMsg {}
+pub struct InitMsg {
+    // Secret entropy for the viewing keys' seed and the contract's own viewing key
+    pub entropy: String,
+    // Migration contracts and wrapped tokens that redeem, deposit and migration hops can use,
+    // the admin can change them later with set_migrations and set_wrapped_tokens
+    pub migrations: Option<Vec<Migration>>,
+    // Pad handle data and log values to BLOCK_SIZE so that their lengths don't reveal amounts,
+    // on unless set to false
+    pub pad_responses: Option<bool>,
+    pub wrapped_tokens: Option<Vec<WrappedToken>>,
+}
 
+/// Routes log these attributes, a key is logged again for every input or hop it describes:
+/// * route started: `route_input_token`, `route_input_amount` for each input
+/// * hop sent: `hop_index` (from 0), `hop_kind`, `hop_token_in`, `hop_token_out`, `hop_amount_in`
+/// * fee taken from the output: `fee_token`, `fee_amount`
+/// * payout: `payout_recipient`, `payout_token`, `return_amount`
+///
+/// `hop_kind` is one of `secret_swap`, `shade_swap`, `sienna_swap`, `sienna_swap_router`,
+/// `shade_protocol_router`, `migration`, `withdraw_liquidity`, `staking_derivative`,
+/// `redeem` or `deposit`.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleMsg {
@@ -15,7 +43,44 @@ pub enum HandleMsg {
         msg: Option<Binary>,
         amount: Uint128,
     },
+    CancelDca {
+        id: u64,
+    },
+    // Refunds the inputs received so far
+    CancelMultiInputRoute {},
+    CancelOrder {
+        id: u64,
+    },
+    // Native inputs are sent with this message, SNIP-20 inputs are sent afterwards with CollectInput
+    CreateMultiInputRoute {
+        route: MultiInputRoute,
+    },
+    CreateViewingKey {
+        entropy: String,
+        padding: Option<String>,
+    },
+    ExecuteArbitrage {
+        amount: Uint128,
+        route: Route,
+    },
+    ExecuteDca {
+        id: u64,
+        route: Route,
+    },
+    ExecuteOrder {
+        id: u64,
+        route: Route,
+    },
     FinalizeRoute {},
+    // Only callable by this contract, records its balance of a token before it's minted
+    RecordBalance {
+        token: SecretContract,
+    },
+    // Fetch the tokens' metadata again, the tokens must be registered
+    RefreshTokens {
+        tokens: Vec<HumanAddr>,
+    },
+    // Registers receive and caches the tokens' metadata
     RegisterTokens {
         tokens: Vec<SecretContract>,
     },
@@ -24,12 +89,199 @@ pub enum HandleMsg {
         denom: Option<String>,
         token: Option<SecretContract>,
     },
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
+    SetKeepers {
+        keepers: Vec<HumanAddr>,
+    },
+    // Replaces the migrations that hops can use
+    SetMigrations {
+        migrations: Vec<Migration>,
+    },
+    // None removes the oracle
+    SetPairOracle {
+        input_token: HumanAddr,
+        output_token: HumanAddr,
+        pair_oracle: Option<PairOracle>,
+    },
+    // Only callable by this contract, sends the amount of a token minted since RecordBalance to itself,
+    // or to the user when it is the output of a zap in
+    SendMintedBalance {
+        token: SecretContract,
+    },
+    SetViewingKey {
+        key: String,
+        padding: Option<String>,
+    },
+    // Replaces the wrapped tokens that hops can redeem or deposit
+    SetWrappedTokens {
+        wrapped_tokens: Vec<WrappedToken>,
+    },
+    // Only callable by this contract while running SwapMany
+    StartRoute {
+        amount: Uint128,
+        route: Route,
+        user: HumanAddr,
+    },
+    SwapMany {
+        routes: Vec<SwapManyRoute>,
+    },
+}
+
+// SNIP-20 inputs are transferred from the sender with an allowance,
+// native inputs are sent with the message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapManyRoute {
+    pub amount: Uint128,
+    pub route: Route,
+}
+
+// Receive msgs other than a route
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    // An input of the sender's multi-input route
+    CollectInput {},
+    CreateDca {
+        route_template: Route,
+        schedule: DcaSchedule,
+    },
+    PlaceLimitOrder {
+        route_template: Route,
+        min_output: Uint128,
+        expiration: Option<u64>,
+    },
+    // The route template's minimum_acceptable_amount bounds the execution
+    PlaceTriggerOrder {
+        route_template: Route,
+        trigger: Trigger,
+        expiration: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleAnswer {
+    CreateViewingKey { key: ViewingKey },
+    RevokePermit { status: ResponseStatus },
+    SetViewingKey { status: ResponseStatus },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Success,
+    Failure,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractInfo {
+    pub version: String,
+    pub hop_kinds: Vec<String>,
+    pub protocols: Vec<Protocol>,
+    pub limits: ContractLimits,
+    pub status: ContractStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractLimits {
+    pub min_hops: u32,
+    // None as the number of hops isn't limited
+    pub max_hops: Option<u32>,
+    pub keeper_fee_basis_points: u64,
+    // Handle data and log values are padded to this size when pad_responses is set
+    pub block_size: u32,
+}
+
+// A route state that is still stored between transactions was left behind by a route
+// that wasn't finalized
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractStatus {
+    Operational,
+    RouteStateLeftBehind,
+}
+
+// Returned as data by the hop that completes a route.
+// For zap in routes the output is the liquidity token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapResult {
+    pub return_amount: Uint128,
+    pub fee_amount: Uint128,
+    pub output_token: HumanAddr,
+    pub hops_executed: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     Config {},
+    ContractInfo {},
+    // Authenticated with the address's viewing key
+    Dcas {
+        address: HumanAddr,
+        key: String,
+        page: u32,
+        page_size: u32,
+    },
+    // Authenticated with the address's viewing key
+    MultiInputCollection {
+        address: HumanAddr,
+        key: String,
+    },
+    // Authenticated with the address's viewing key
+    Orders {
+        address: HumanAddr,
+        key: String,
+        page: u32,
+        page_size: u32,
+    },
+    PairOracle {
+        input_token: HumanAddr,
+        output_token: HumanAddr,
+    },
+    // Authenticated with the admin's viewing key.
+    // Queries run between transactions, so any route state returned was left behind by a route
+    // that wasn't finalized.
+    RouteState {
+        key: String,
+    },
+    // Aggregated over all users
+    Stats {},
+    // Newest first, authenticated with the address's viewing key
+    SwapHistory {
+        address: HumanAddr,
+        key: String,
+        page: u32,
+        page_size: u32,
+    },
+    // Aggregated over all users, zero for tokens that haven't been swapped
+    TokenStats {
+        token: HumanAddr,
+    },
+    // Metadata of the registered tokens, in the order they were registered
+    Tokens {
+        page: u32,
+        page_size: u32,
+    },
+    // SNIP-24 permit signed by the address being queried
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+    },
+}
+
+// The per-user queries, for the permit's signer.
+// Swap history needs the history permission, the rest need the owner permission.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    Dcas { page: u32, page_size: u32 },
+    MultiInputCollection {},
+    Orders { page: u32, page_size: u32 },
+    SwapHistory { page: u32, page_size: u32 },
 }
 
 // Adapted from https://github.com/scrtlabs/secret-toolkit/blob/master/packages/snip20/src/handle.rs
@@ -42,31 +294,71 @@ pub enum Snip20 {
 impl Snip20 {
     pub fn to_cosmos_msg(
         &self,
-        mut block_size: usize,
+        block_size: usize,
         callback_code_hash: String,
         contract_addr: HumanAddr,
         coin: Option<Coin>,
     ) -> StdResult<CosmosMsg> {
-        // can not have block size of 0
-        if block_size == 0 {
-            block_size = 1;
-        }
-        let mut msg = to_binary(self)?;
-        space_pad(&mut msg.0, block_size);
-        let mut send = Vec::new();
-        if let Some(coin_unwrapped) = coin {
-            send.push(coin_unwrapped);
-        }
-        let execute = WasmMsg::Execute {
-            contract_addr,
-            callback_code_hash,
-            msg,
-            send,
-        };
-        Ok(execute.into())
+        to_cosmos_msg_with_coin(self, block_size, callback_code_hash, contract_addr, coin)
+    }
+}
+
+// HandleCallback::to_cosmos_msg can't send a coin with the message
+fn to_cosmos_msg_with_coin<T: Serialize>(
+    msg: &T,
+    mut block_size: usize,
+    callback_code_hash: String,
+    contract_addr: HumanAddr,
+    coin: Option<Coin>,
+) -> StdResult<CosmosMsg> {
+    // can not have block size of 0
+    if block_size == 0 {
+        block_size = 1;
+    }
+    let mut msg = to_binary(msg)?;
+    space_pad(&mut msg.0, block_size);
+    let mut send = Vec::new();
+    if let Some(coin_unwrapped) = coin {
+        send.push(coin_unwrapped);
     }
+    let execute = WasmMsg::Execute {
+        contract_addr,
+        callback_code_hash,
+        msg,
+        send,
+    };
+    Ok(execute.into())
+}
+
+// https://github.com/enigmampc/SecretSwap/blob/master/packages/secretswap/src/pair.rs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSwapPair {
+    ProvideLiquidity {
+        assets: [Asset; 2],
+        slippage_tolerance: Option<Decimal>,
+    },
+}
+impl HandleCallback for SecretSwapPair {
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSwapPairQuery {
+    Pool {},
+}
+impl Query for SecretSwapPairQuery {
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SecretSwapPoolResponse {
+    pub assets: [Asset; 2],
+    pub total_share: Uint128,
+}
+
+// https://github.com/enigmampc/SecretSwap/blob/master/packages/secretswap/src/pair.rs
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Snip20Swap {
@@ -74,6 +366,7 @@ pub enum Snip20Swap {
         expected_return: Option<Uint128>,
         to: Option<HumanAddr>,
     },
+    WithdrawLiquidity {},
 }
 
 // https://github.com/securesecrets/shadeswap/blob/main/contracts/router/src/contract.rs
@@ -84,3 +377,68 @@ pub enum ShadeProtocol {
         path: Vec<SecretContractForShadeProtocol>,
     },
 }
+
+// https://github.com/securesecrets/shadeswap/blob/main/contracts/amm_pair/src/contract.rs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadeProtocolPair {
+    SwapTokens {
+        expected_return: Option<Uint128>,
+        to: Option<String>,
+    },
+}
+
+// https://github.com/SiennaNetwork/SiennaNetwork/blob/main/libraries/amm-shared/src/msg/exchange.rs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SiennaSwapPair {
+    Swap {
+        expected_return: Option<Uint128>,
+        to: Option<HumanAddr>,
+    },
+}
+
+// https://github.com/SiennaNetwork/SiennaNetwork/blob/main/libraries/amm-shared/src/msg/router.rs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SiennaSwapRoute {
+    pub hops: Vec<SiennaSwapHop>,
+    pub expected_return: Option<Uint128>,
+    pub to: HumanAddr,
+}
+
+// https://github.com/securesecrets/shade/tree/main/contracts/stkd-scrt
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingDerivative {
+    Stake {},
+}
+impl StakingDerivative {
+    pub fn to_cosmos_msg(
+        &self,
+        block_size: usize,
+        callback_code_hash: String,
+        contract_addr: HumanAddr,
+        coin: Option<Coin>,
+    ) -> StdResult<CosmosMsg> {
+        to_cosmos_msg_with_coin(self, block_size, callback_code_hash, contract_addr, coin)
+    }
+}
+
+// Band protocol's standard reference contract, rates have 18 decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OracleQuery {
+    GetReferenceData {
+        base_symbol: String,
+        quote_symbol: String,
+    },
+}
+impl Query for OracleQuery {
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+}
+
+// Only the rate is needed, the other fields in the answer are ignored
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OracleReferenceData {
+    pub rate: Uint128,
+}
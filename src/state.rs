@@ -1,13 +1,49 @@
-use crate::constants::KEY_ROUTE_STATE;
-use cosmwasm_std::{HumanAddr, StdResult, Storage, Uint128};
-use cosmwasm_storage::{singleton, singleton_read};
+use crate::constants::{
+    KEY_CONTRACT_VIEWING_KEY, KEY_DCA_COUNT, KEY_ORDER_COUNT, KEY_PRNG_SEED, KEY_ROUTE_COUNT,
+    KEY_ROUTE_STATE, PREFIX_DCAS, PREFIX_ESCROWED_AMOUNTS, PREFIX_HOP_COUNTS,
+    PREFIX_MULTI_INPUT_COLLECTIONS, PREFIX_ORDERS, PREFIX_PAIR_ORACLES, PREFIX_SWAP_HISTORY,
+    PREFIX_TOKENS, PREFIX_TOKEN_ADDRESSES, PREFIX_TOKEN_STATS, PREFIX_USER_DCAS,
+    PREFIX_USER_ORDERS, PREFIX_VIEWING_KEYS,
+};
+use cosmwasm_std::{HumanAddr, ReadonlyStorage, StdError, StdResult, Storage, Uint128};
+use cosmwasm_storage::{
+    bucket, bucket_read, singleton, singleton_read, PrefixedStorage, ReadonlyPrefixedStorage,
+};
 use schemars::JsonSchema;
+use secret_toolkit::storage::{AppendStore, AppendStoreMut};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Config {
     pub admin: HumanAddr,
+    // Permits must allow this contract's address
+    pub contract_address: HumanAddr,
+    // Addresses allowed to run arbitrage routes with the contract's own inventory
+    pub keepers: Vec<HumanAddr>,
+    // Migration contracts that hops can send tokens to for migrate_to_token
+    pub migrations: Vec<Migration>,
+    // Pad handle data and log values to BLOCK_SIZE
+    pub pad_responses: bool,
+    // SNIP-20s that hops can redeem for, or deposit, a native denom
+    pub wrapped_tokens: Vec<WrappedToken>,
+}
+
+// Migrating sends this contract's own migrate_to_token for from_token,
+// so it's only allowed through whitelisted migration contracts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Migration {
+    pub from_token: HumanAddr,
+    pub smart_contract: HumanAddr,
+    pub migrate_to_token: HumanAddr,
+}
+
+// Redeeming sends this contract's own coins for the redeemed token,
+// so it's only allowed for whitelisted tokens and their denoms
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WrappedToken {
+    pub token: HumanAddr,
+    pub denom: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -17,6 +53,123 @@ pub struct Hop {
     pub redeem_denom: Option<String>,
     pub migrate_to_token: Option<SecretContract>,
     pub shade_protocol_router_path: Option<Vec<SecretContractForShadeProtocol>>,
+    pub protocol: Option<Protocol>,
+    pub sienna_swap_router_path: Option<Vec<SiennaSwapHop>>,
+    pub token_out: Option<SecretContract>,
+    // Passed to the pair as expected_return and checked when the hop's output is received
+    pub minimum_output: Option<Uint128>,
+}
+
+// The protocol of the smart_contract in a hop.
+// None is treated as SecretSwap.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    SecretSwap,
+    ShadeSwap,
+    SiennaSwap,
+    // Liquid staking derivatives e.g. stkd-SCRT minted from the native coin
+    StakingDerivative,
+    // Withdraw liquidity from a SecretSwap pair with its liquidity token and keep token_out,
+    // the other withdrawn token is swapped into token_out through the same pair
+    WithdrawLiquidity,
+}
+
+// The input is escrowed in the contract until a keeper executes the order with a route
+// that reaches min_output, or the owner cancels it.
+// Orders with a trigger are stop-loss or take-profit orders.
+// expiration is a block time in seconds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LimitOrder {
+    pub id: u64,
+    pub owner: HumanAddr,
+    pub route_template: Route,
+    pub amount: Uint128,
+    pub denom: Option<String>,
+    pub min_output: Uint128,
+    pub expiration: Option<u64>,
+    pub trigger: Option<Trigger>,
+}
+
+// Admin configured oracle for routes from one token to another.
+// The oracle's rate is the price of base_symbol (the input) in quote_symbol (the output),
+// routes can't return less than that rate minus max_deviation_basis_points.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairOracle {
+    pub oracle: SecretContract,
+    pub base_symbol: String,
+    pub quote_symbol: String,
+    pub input_decimals: u8,
+    pub output_decimals: u8,
+    pub max_deviation_basis_points: u16,
+}
+
+// Stop-loss and take-profit orders can only be executed once the oracle's rate
+// of base_symbol in quote_symbol crosses the trigger's rate
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Trigger {
+    pub oracle: SecretContract,
+    pub base_symbol: String,
+    pub quote_symbol: String,
+    pub kind: TriggerKind,
+    pub rate: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerKind {
+    // Executable when the rate is at or below the trigger's rate
+    StopLoss,
+    // Executable when the rate is at or above the trigger's rate
+    TakeProfit,
+}
+
+// Recurring swaps of the schedule's amount_per_period from the escrowed budget, executed by keepers
+// once every period with a route that reaches min_output_per_period.
+// next_execution is a block height or time depending on the period.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Dca {
+    pub id: u64,
+    pub owner: HumanAddr,
+    pub route_template: Route,
+    pub budget: Uint128,
+    pub denom: Option<String>,
+    pub schedule: DcaSchedule,
+    pub next_execution: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DcaSchedule {
+    pub amount_per_period: Uint128,
+    pub period: DcaPeriod,
+    pub min_output_per_period: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DcaPeriod {
+    Blocks(u64),
+    Seconds(u64),
+}
+
+// Several inputs swapped into one output token.
+// The outputs are summed before being checked against minimum_acceptable_amount.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MultiInputRoute {
+    pub inputs: Vec<RouteInput>,
+    pub estimated_amount: Uint128,
+    pub minimum_acceptable_amount: Uint128,
+    pub to: HumanAddr,
+    pub allow_third_party_recipient: Option<bool>,
+    pub private: Option<bool>,
+}
+
+// A multi-input route waiting for its SNIP-20 inputs to be received
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MultiInputCollection {
+    pub owner: HumanAddr,
+    pub route: MultiInputRoute,
+    pub received: Vec<bool>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -25,12 +178,103 @@ pub struct Route {
     pub estimated_amount: Uint128,
     pub minimum_acceptable_amount: Uint128,
     pub to: HumanAddr,
+    pub allow_third_party_recipient: Option<bool>,
+    pub zap_in: Option<ZapIn>,
+    // Arbitrage mode: the route must start and end with the same token
+    // and return at least the input amount plus min_profit
+    pub min_profit: Option<Uint128>,
+    // Private mode: amounts are only returned in data and the user's history, never logged
+    pub private: Option<bool>,
+}
+
+// One input of a multi-input route and the hops that swap it into the output token
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RouteInput {
+    pub amount: Uint128,
+    pub hops: VecDeque<Hop>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct RouteState {
     pub current_hop: Hop,
+    // The index logged with the next hop that is sent
+    pub hop_index: u32,
     pub remaining_route: Route,
+    // The half of the route's output kept to provide liquidity with, while the other half is swapped
+    pub zap_in_retained_amount: Option<Uint128>,
+    // The withdrawn amount kept while waiting for the rest of the zap out to be received
+    pub zap_out_retained_amount: Option<Uint128>,
+    // This contract's balance of a token that is minted without calling Receive,
+    // recorded before minting so that the minted amount is the increase
+    pub balance_before_mint: Option<Uint128>,
+    // The keeper executing a limit order, paid a fee from the output
+    pub keeper: Option<HumanAddr>,
+    // The lowest output allowed by the oracle configured for the route's input and output tokens
+    pub oracle_minimum_amount: Option<Uint128>,
+    // The inputs of a multi-input route that are swapped after the current one
+    pub remaining_inputs: VecDeque<RouteInput>,
+    // The summed outputs of the multi-input route's inputs that have been swapped
+    pub collected_amount: Option<Uint128>,
+    // The user whose swap history the route is recorded in
+    pub user: HumanAddr,
+    // Recorded in the user's swap history when the route pays out
+    pub swap: Swap,
+}
+
+// A completed route in a user's swap history
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Swap {
+    pub inputs: Vec<TokenAmount>,
+    pub output: TokenAmount,
+    pub fee_amount: Uint128,
+    pub block_height: u64,
+    pub hop_count: u32,
+}
+
+// Totals over every completed route, without any per-user data.
+// Volumes and fees are kept per token, see TokenStats.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Stats {
+    pub route_count: u64,
+    pub hop_counts: Vec<HopCount>,
+}
+
+// Every kind that hop_kind can log is listed, including unused ones
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HopCount {
+    pub hop_kind: String,
+    pub hop_count: u64,
+}
+
+// Totals for one token over every completed route
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct TokenStats {
+    pub input_volume: Uint128,
+    pub output_volume: Uint128,
+    // Fees are taken from the output so they are counted for the output token
+    pub fees: Uint128,
+}
+
+// Fetched from the token's token_info when it's registered or refreshed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TokenMetadata {
+    pub token: SecretContract,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct TokenAmount {
+    pub token: HumanAddr,
+    pub amount: Uint128,
+}
+
+// Provide liquidity to a SecretSwap pair with the route's output and send the liquidity token to `to`
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, JsonSchema)]
+pub struct ZapIn {
+    pub pair: SecretContract,
+    pub liquidity_token: SecretContract,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, JsonSchema)]
@@ -45,6 +289,26 @@ pub struct SecretContractForShadeProtocol {
     pub code_hash: String,
 }
 
+// https://github.com/SiennaNetwork/SiennaNetwork/blob/main/libraries/amm-shared/src/msg/router.rs
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, JsonSchema)]
+pub struct SiennaSwapHop {
+    pub from_token: SiennaSwapTokenType,
+    pub pair_address: HumanAddr,
+    pub pair_code_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SiennaSwapTokenType {
+    CustomToken {
+        contract_addr: HumanAddr,
+        token_code_hash: String,
+    },
+    NativeToken {
+        denom: String,
+    },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Token {
@@ -63,3 +327,285 @@ pub fn read_route_state<S: Storage>(storage: &S) -> StdResult<Option<RouteState>
 pub fn delete_route_state<S: Storage>(storage: &mut S) {
     singleton::<S, Option<RouteState>>(storage, KEY_ROUTE_STATE).remove();
 }
+
+pub fn store_route_count<S: Storage>(storage: &mut S, route_count: u64) -> StdResult<()> {
+    singleton(storage, KEY_ROUTE_COUNT).save(&route_count)
+}
+
+pub fn read_route_count<S: Storage>(storage: &S) -> StdResult<u64> {
+    Ok(singleton_read(storage, KEY_ROUTE_COUNT)
+        .may_load()?
+        .unwrap_or(0))
+}
+
+pub fn store_hop_count<S: Storage>(
+    storage: &mut S,
+    hop_kind: &str,
+    hop_count: u64,
+) -> StdResult<()> {
+    bucket(PREFIX_HOP_COUNTS, storage).save(hop_kind.as_bytes(), &hop_count)
+}
+
+pub fn read_hop_count<S: Storage>(storage: &S, hop_kind: &str) -> StdResult<u64> {
+    Ok(bucket_read(PREFIX_HOP_COUNTS, storage)
+        .may_load(hop_kind.as_bytes())?
+        .unwrap_or(0))
+}
+
+pub fn store_token_stats<S: Storage>(
+    storage: &mut S,
+    token: &HumanAddr,
+    token_stats: &TokenStats,
+) -> StdResult<()> {
+    bucket(PREFIX_TOKEN_STATS, storage).save(token.0.as_bytes(), token_stats)
+}
+
+pub fn read_token_stats<S: Storage>(storage: &S, token: &HumanAddr) -> StdResult<TokenStats> {
+    Ok(bucket_read(PREFIX_TOKEN_STATS, storage)
+        .may_load(token.0.as_bytes())?
+        .unwrap_or_default())
+}
+
+pub fn next_order_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    next_id(storage, KEY_ORDER_COUNT)
+}
+
+pub fn store_order<S: Storage>(storage: &mut S, order: &LimitOrder) -> StdResult<()> {
+    bucket(PREFIX_ORDERS, storage).save(&order.id.to_be_bytes(), order)?;
+    add_user_id(storage, PREFIX_USER_ORDERS, &order.owner, order.id)
+}
+
+pub fn read_order<S: Storage>(storage: &S, id: u64) -> StdResult<Option<LimitOrder>> {
+    bucket_read(PREFIX_ORDERS, storage).may_load(&id.to_be_bytes())
+}
+
+pub fn delete_order<S: Storage>(storage: &mut S, order: &LimitOrder) -> StdResult<()> {
+    bucket::<S, LimitOrder>(PREFIX_ORDERS, storage).remove(&order.id.to_be_bytes());
+    remove_user_id(storage, PREFIX_USER_ORDERS, &order.owner, order.id)
+}
+
+pub fn read_user_order_ids<S: Storage>(storage: &S, owner: &HumanAddr) -> StdResult<Vec<u64>> {
+    read_user_ids(storage, PREFIX_USER_ORDERS, owner)
+}
+
+pub fn next_dca_id<S: Storage>(storage: &mut S) -> StdResult<u64> {
+    next_id(storage, KEY_DCA_COUNT)
+}
+
+pub fn store_dca<S: Storage>(storage: &mut S, dca: &Dca) -> StdResult<()> {
+    bucket(PREFIX_DCAS, storage).save(&dca.id.to_be_bytes(), dca)?;
+    add_user_id(storage, PREFIX_USER_DCAS, &dca.owner, dca.id)
+}
+
+pub fn read_dca<S: Storage>(storage: &S, id: u64) -> StdResult<Option<Dca>> {
+    bucket_read(PREFIX_DCAS, storage).may_load(&id.to_be_bytes())
+}
+
+pub fn delete_dca<S: Storage>(storage: &mut S, dca: &Dca) -> StdResult<()> {
+    bucket::<S, Dca>(PREFIX_DCAS, storage).remove(&dca.id.to_be_bytes());
+    remove_user_id(storage, PREFIX_USER_DCAS, &dca.owner, dca.id)
+}
+
+pub fn read_user_dca_ids<S: Storage>(storage: &S, owner: &HumanAddr) -> StdResult<Vec<u64>> {
+    read_user_ids(storage, PREFIX_USER_DCAS, owner)
+}
+
+fn next_id<S: Storage>(storage: &mut S, key: &[u8]) -> StdResult<u64> {
+    let id: u64 = singleton_read(storage, key).may_load()?.unwrap_or(0);
+    singleton(storage, key).save(&(id + 1))?;
+    Ok(id)
+}
+
+fn add_user_id<S: Storage>(
+    storage: &mut S,
+    prefix: &[u8],
+    owner: &HumanAddr,
+    id: u64,
+) -> StdResult<()> {
+    let mut ids: Vec<u64> = read_user_ids(storage, prefix, owner)?;
+    if !ids.contains(&id) {
+        ids.push(id);
+    }
+    bucket(prefix, storage).save(owner.0.as_bytes(), &ids)
+}
+
+fn remove_user_id<S: Storage>(
+    storage: &mut S,
+    prefix: &[u8],
+    owner: &HumanAddr,
+    id: u64,
+) -> StdResult<()> {
+    let mut ids: Vec<u64> = read_user_ids(storage, prefix, owner)?;
+    ids.retain(|user_id| *user_id != id);
+    bucket(prefix, storage).save(owner.0.as_bytes(), &ids)
+}
+
+fn read_user_ids<S: Storage>(storage: &S, prefix: &[u8], owner: &HumanAddr) -> StdResult<Vec<u64>> {
+    Ok(bucket_read(prefix, storage)
+        .may_load(owner.0.as_bytes())?
+        .unwrap_or_default())
+}
+
+pub fn store_multi_input_collection<S: Storage>(
+    storage: &mut S,
+    collection: &MultiInputCollection,
+) -> StdResult<()> {
+    bucket(PREFIX_MULTI_INPUT_COLLECTIONS, storage).save(collection.owner.0.as_bytes(), collection)
+}
+
+pub fn read_multi_input_collection<S: Storage>(
+    storage: &S,
+    owner: &HumanAddr,
+) -> StdResult<Option<MultiInputCollection>> {
+    bucket_read(PREFIX_MULTI_INPUT_COLLECTIONS, storage).may_load(owner.0.as_bytes())
+}
+
+pub fn delete_multi_input_collection<S: Storage>(storage: &mut S, owner: &HumanAddr) {
+    bucket::<S, MultiInputCollection>(PREFIX_MULTI_INPUT_COLLECTIONS, storage)
+        .remove(owner.0.as_bytes());
+}
+
+pub fn store_pair_oracle<S: Storage>(
+    storage: &mut S,
+    input_token: &HumanAddr,
+    output_token: &HumanAddr,
+    pair_oracle: Option<PairOracle>,
+) -> StdResult<()> {
+    let key: Vec<u8> = pair_oracle_key(input_token, output_token);
+    match pair_oracle {
+        Some(pair_oracle) => bucket(PREFIX_PAIR_ORACLES, storage).save(&key, &pair_oracle),
+        None => {
+            bucket::<S, PairOracle>(PREFIX_PAIR_ORACLES, storage).remove(&key);
+            Ok(())
+        }
+    }
+}
+
+pub fn read_pair_oracle<S: Storage>(
+    storage: &S,
+    input_token: &HumanAddr,
+    output_token: &HumanAddr,
+) -> StdResult<Option<PairOracle>> {
+    bucket_read(PREFIX_PAIR_ORACLES, storage).may_load(&pair_oracle_key(input_token, output_token))
+}
+
+fn pair_oracle_key(input_token: &HumanAddr, output_token: &HumanAddr) -> Vec<u8> {
+    [input_token.0.as_bytes(), b"/", output_token.0.as_bytes()].concat()
+}
+
+// The total held for orders, DCAs and multi-input collections, keyed by SNIP-20 address or native denom
+pub fn read_escrowed_amount<S: Storage>(storage: &S, key: &str) -> StdResult<Uint128> {
+    Ok(bucket_read(PREFIX_ESCROWED_AMOUNTS, storage)
+        .may_load(key.as_bytes())?
+        .unwrap_or_else(Uint128::zero))
+}
+
+pub fn add_escrowed_amount<S: Storage>(
+    storage: &mut S,
+    key: &str,
+    amount: Uint128,
+) -> StdResult<()> {
+    let escrowed_amount: Uint128 = read_escrowed_amount(storage, key)? + amount;
+    bucket(PREFIX_ESCROWED_AMOUNTS, storage).save(key.as_bytes(), &escrowed_amount)
+}
+
+pub fn remove_escrowed_amount<S: Storage>(
+    storage: &mut S,
+    key: &str,
+    amount: Uint128,
+) -> StdResult<()> {
+    let escrowed_amount: Uint128 = (read_escrowed_amount(storage, key)? - amount)?;
+    bucket(PREFIX_ESCROWED_AMOUNTS, storage).save(key.as_bytes(), &escrowed_amount)
+}
+
+pub fn append_swap<S: Storage>(storage: &mut S, user: &HumanAddr, swap: &Swap) -> StdResult<()> {
+    let mut store = PrefixedStorage::multilevel(&[PREFIX_SWAP_HISTORY, user.0.as_bytes()], storage);
+    AppendStoreMut::attach_or_create(&mut store)?.push(swap)
+}
+
+// Newest first
+pub fn read_swaps<S: Storage>(
+    storage: &S,
+    user: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Vec<Swap>> {
+    let store =
+        ReadonlyPrefixedStorage::multilevel(&[PREFIX_SWAP_HISTORY, user.0.as_bytes()], storage);
+    let store: AppendStore<Swap, _> = match AppendStore::attach(&store) {
+        Some(store) => store?,
+        None => return Ok(vec![]),
+    };
+    store
+        .iter()
+        .rev()
+        .skip((page as usize).saturating_mul(page_size as usize))
+        .take(page_size as usize)
+        .collect()
+}
+
+pub fn store_prng_seed<S: Storage>(storage: &mut S, seed: &[u8]) {
+    storage.set(KEY_PRNG_SEED, seed);
+}
+
+pub fn read_prng_seed<S: Storage>(storage: &S) -> StdResult<Vec<u8>> {
+    storage
+        .get(KEY_PRNG_SEED)
+        .ok_or_else(|| StdError::generic_err("PRNG seed not found."))
+}
+
+// This contract's own viewing key, set on registered tokens so that it can read its balances
+pub fn store_contract_viewing_key<S: Storage>(storage: &mut S, key: &str) {
+    storage.set(KEY_CONTRACT_VIEWING_KEY, key.as_bytes());
+}
+
+pub fn read_contract_viewing_key<S: Storage>(storage: &S) -> StdResult<String> {
+    storage
+        .get(KEY_CONTRACT_VIEWING_KEY)
+        .map(|key| String::from_utf8_lossy(&key).to_string())
+        .ok_or_else(|| StdError::generic_err("Contract viewing key not found."))
+}
+
+pub fn store_viewing_key<S: Storage>(storage: &mut S, owner: &HumanAddr, hashed_key: &[u8]) {
+    PrefixedStorage::new(PREFIX_VIEWING_KEYS, storage).set(owner.0.as_bytes(), hashed_key);
+}
+
+pub fn read_viewing_key<S: Storage>(storage: &S, owner: &HumanAddr) -> Option<Vec<u8>> {
+    ReadonlyPrefixedStorage::new(PREFIX_VIEWING_KEYS, storage).get(owner.0.as_bytes())
+}
+
+pub fn store_token_metadata<S: Storage>(
+    storage: &mut S,
+    metadata: &TokenMetadata,
+) -> StdResult<()> {
+    let address: &HumanAddr = &metadata.token.address;
+    if read_token_metadata(storage, address)?.is_none() {
+        let mut store = PrefixedStorage::new(PREFIX_TOKEN_ADDRESSES, storage);
+        AppendStoreMut::attach_or_create(&mut store)?.push(address)?;
+    }
+    bucket(PREFIX_TOKENS, storage).save(address.0.as_bytes(), metadata)
+}
+
+pub fn read_token_metadata<S: Storage>(
+    storage: &S,
+    address: &HumanAddr,
+) -> StdResult<Option<TokenMetadata>> {
+    bucket_read(PREFIX_TOKENS, storage).may_load(address.0.as_bytes())
+}
+
+pub fn read_token_addresses<S: Storage>(
+    storage: &S,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Vec<HumanAddr>> {
+    let store = ReadonlyPrefixedStorage::new(PREFIX_TOKEN_ADDRESSES, storage);
+    let store: AppendStore<HumanAddr, _> = match AppendStore::attach(&store) {
+        Some(store) => store?,
+        None => return Ok(vec![]),
+    };
+    store
+        .iter()
+        .skip((page as usize).saturating_mul(page_size as usize))
+        .take(page_size as usize)
+        .collect()
+}
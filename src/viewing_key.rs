@@ -0,0 +1,39 @@
+// Adapted from https://github.com/scrtlabs/snip20-reference-impl/blob/master/src/viewing_key.rs
+use cosmwasm_std::{Binary, Env};
+use schemars::JsonSchema;
+use secret_toolkit::crypto::{sha_256, Prng};
+use serde::{Deserialize, Serialize};
+
+pub const VIEWING_KEY_PREFIX: &str = "api_key_";
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ViewingKey(pub String);
+
+impl ViewingKey {
+    pub fn new(env: &Env, seed: &[u8], entropy: &[u8]) -> Self {
+        let mut rng_entropy: Vec<u8> = vec![];
+        rng_entropy.extend_from_slice(&env.block.height.to_be_bytes());
+        rng_entropy.extend_from_slice(&env.block.time.to_be_bytes());
+        rng_entropy.extend_from_slice(env.message.sender.0.as_bytes());
+        rng_entropy.extend_from_slice(entropy);
+        let mut rng: Prng = Prng::new(seed, &rng_entropy);
+        let key: [u8; 32] = sha_256(&rng.rand_bytes());
+
+        Self(VIEWING_KEY_PREFIX.to_string() + &Binary::from(&key[..]).to_base64())
+    }
+
+    pub fn to_hashed(&self) -> [u8; 32] {
+        sha_256(self.0.as_bytes())
+    }
+
+    // Compare in constant time so that the stored hash can't be guessed by timing queries
+    pub fn check_viewing_key(&self, hashed: &[u8]) -> bool {
+        let mine: [u8; 32] = self.to_hashed();
+        mine.len() == hashed.len()
+            && mine
+                .iter()
+                .zip(hashed.iter())
+                .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+}